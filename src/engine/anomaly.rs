@@ -0,0 +1,182 @@
+use super::graph::{Node, NodeId, OpKind};
+use super::Float;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+thread_local! {
+    static ANOMALY_ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Restores the previous anomaly mode of the current thread when dropped.
+#[must_use = "anomaly detection is turned off as soon as the guard is dropped"]
+pub struct AnomalyGuard {
+    prev: bool,
+}
+
+/// Turns on anomaly detection on the current thread until the returned
+/// guard is dropped. While it is alive, `try_backward` on `engine::v0` and
+/// `engine::v1` values checks the data of every node and every gradient an
+/// op's backward rule produces, and stops at the first one that is NaN or
+/// infinite; `backward` panics with the same report. Guards nest.
+pub fn detect_anomaly() -> AnomalyGuard {
+    AnomalyGuard {
+        prev: ANOMALY_ENABLED.with(|enabled| enabled.replace(true)),
+    }
+}
+
+/// Whether `backward` on the current thread checks for non-finite values.
+pub fn is_anomaly_enabled() -> bool {
+    ANOMALY_ENABLED.with(|enabled| enabled.get())
+}
+
+impl Drop for AnomalyGuard {
+    fn drop(&mut self) {
+        ANOMALY_ENABLED.with(|enabled| enabled.set(self.prev));
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Forward,
+    Backward,
+}
+
+/// The first non-finite value found in anomaly mode: the op that produced
+/// it, in the forward pass or in its backward rule, the data of the op's
+/// inputs, and the ops on the way from the root down to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly<T> {
+    pub phase: Phase,
+    pub op: OpKind<T>,
+    pub inputs: Vec<T>,
+    pub value: T,
+    pub path: Vec<OpKind<T>>,
+}
+
+/// The first node of `nodes`, in topological order, whose data is not
+/// finite. Its inputs are all finite, so its op is where the value went bad.
+pub(crate) fn check_forward<T: Float>(nodes: &[Node<T>]) -> Result<(), Anomaly<T>> {
+    match nodes.iter().find(|node| !node.data.is_finite()) {
+        Some(node) => Err(report(nodes, node.id, Phase::Forward, node.data)),
+        None => Ok(()),
+    }
+}
+
+/// Describes the non-finite `value` that node `id` produced during `phase`.
+/// `nodes` is the graph in topological order, ending with the root.
+pub(crate) fn report<T: Float>(
+    nodes: &[Node<T>],
+    id: NodeId,
+    phase: Phase,
+    value: T,
+) -> Anomaly<T> {
+    let index: HashMap<NodeId, &Node<T>> = nodes.iter().map(|node| (node.id, node)).collect();
+    let node = index[&id];
+
+    // Walk down from the root remembering how each node was first reached,
+    // then follow those links back up from `id`.
+    let root = nodes.last().unwrap().id;
+    let mut reached_from: HashMap<NodeId, NodeId> = HashMap::new();
+    let mut stack: Vec<NodeId> = vec![root];
+    while let Some(v) = stack.pop() {
+        for &p in index[&v].parents.iter() {
+            if p != root && !reached_from.contains_key(&p) {
+                reached_from.insert(p, v);
+                stack.push(p);
+            }
+        }
+    }
+
+    let mut path: Vec<OpKind<T>> = vec![node.op.clone()];
+    let mut v = id;
+    while let Some(&next) = reached_from.get(&v) {
+        path.push(index[&next].op.clone());
+        v = next;
+    }
+    path.reverse();
+
+    Anomaly {
+        phase,
+        op: node.op.clone(),
+        inputs: node.parents.iter().map(|p| index[p].data).collect(),
+        value,
+        path,
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Forward => f.write_str("forward pass"),
+            Phase::Backward => f.write_str("backward pass"),
+        }
+    }
+}
+
+impl<T: Float> Display for Anomaly<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path: Vec<String> = self.path.iter().map(|op| op.to_string()).collect();
+
+        write!(
+            f,
+            "{} of {} on inputs {:?} gave {:?} (path from root: {})",
+            self.phase,
+            self.op,
+            self.inputs,
+            self.value,
+            path.join(" -> ")
+        )
+    }
+}
+
+impl<T: Float> std::error::Error for Anomaly<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guards_nest_and_restore() {
+        assert!(!is_anomaly_enabled());
+        {
+            let _outer = detect_anomaly();
+            {
+                let _inner = detect_anomaly();
+                assert!(is_anomaly_enabled());
+            }
+            assert!(is_anomaly_enabled());
+        }
+        assert!(!is_anomaly_enabled());
+    }
+    #[test]
+    fn report_follows_the_path_from_the_root() {
+        let node = |id, op, parents: Vec<usize>, data| Node {
+            id: NodeId(id),
+            op,
+            parents: parents.into_iter().map(NodeId).collect(),
+            data,
+            grad: 0.0,
+            label: None,
+        };
+        let nodes = vec![
+            node(0, OpKind::Leaf, vec![], -1.0),
+            node(1, OpKind::Powf(0.5), vec![0], f64::NAN),
+            node(2, OpKind::Tanh, vec![1], f64::NAN),
+            node(3, OpKind::Add, vec![2, 0], f64::NAN),
+        ];
+
+        let anomaly = check_forward(&nodes).unwrap_err();
+
+        assert_eq!(OpKind::Powf(0.5), anomaly.op);
+        assert_eq!(vec![-1.0], anomaly.inputs);
+        assert_eq!(
+            vec![OpKind::Add, OpKind::Tanh, OpKind::Powf(0.5)],
+            anomaly.path
+        );
+        assert_eq!(
+            "forward pass of Powf(0.5) on inputs [-1.0] gave NaN (path from root: Add -> Tanh -> Powf(0.5))",
+            anomaly.to_string()
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use super::Float;
+use crate::error::Error;
+
+/// The handful of operations `nn` needs from a scalar autograd engine.
+/// Implemented by `engine::v0::Value`, `engine::v1::Value` and
+/// `engine::sync::Value`, so that `nn::mlp::MLP` can build the same model on
+/// any of them.
+pub trait Backend<T: Float = f64>: Clone {
+    /// A new leaf holding `data`, with a zero gradient.
+    fn leaf(data: T) -> Self;
+
+    fn add(self, other: Self) -> Self;
+
+    fn mul(self, other: Self) -> Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.add(other.mul(Self::leaf(-T::one())))
+    }
+
+    fn powf(self, n: T) -> Self;
+
+    fn tanh(self) -> Self;
+
+    /// Adds the gradient of `self` to the `grad` of every node of its
+    /// graph and returns the node to read the rest of the graph from: a
+    /// clone of `self` for `engine::v1` and `engine::sync`, the copy of the graph its own
+    /// `backward` builds for `engine::v0`.
+    fn backward(&self) -> Self {
+        self.try_backward().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// `backward`, returning the engine's error instead of panicking.
+    fn try_backward(&self) -> Result<Self, Error<T>>;
+
+    fn data(&self) -> T;
+
+    /// The gradient accumulated since the last `zero_grad`: `grad` for
+    /// `engine::v1` and `engine::sync`, `total_grad()` for `engine::v0`.
+    fn grad(&self) -> T;
+
+    /// Overwrites the data of a leaf in place, e.g. for a parameter update.
+    fn set_data(&mut self, data: T);
+
+    /// Resets the gradient of every node of the graph behind `self`.
+    fn zero_grad(&self);
+}
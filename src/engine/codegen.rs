@@ -0,0 +1,602 @@
+use super::program::{Op, Program};
+use super::{lit, Float};
+use crate::error::Error;
+use std::any::type_name;
+
+/// The language `Program::to_rust` and `Program::to_c` emit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Lang {
+    Rust,
+    C,
+}
+
+impl<T: Float> Program<T> {
+    /// A standalone Rust function `name` computing the outputs of the
+    /// program from its inputs, with the params baked in as constants:
+    /// `fn name(x: &[f64; N]) -> [f64; M]`, or with `f32` for a
+    /// `Program<f32>`. With `gradients` it also returns the gradients of the
+    /// sum of the outputs with respect to the inputs and the params, as
+    /// `backward` computes them: `-> ([f64; M], [f64; N], [f64; P])`. The
+    /// same ops are evaluated in the same order as `forward` and `backward`,
+    /// so the results are identical. Fails on a custom op.
+    pub fn to_rust(&self, name: &str, gradients: bool) -> Result<String, Error<T>> {
+        self._generate(Lang::Rust, name, gradients)
+    }
+
+    /// Like `to_rust` but a C function using only `<math.h>`: `void
+    /// name(const double x[N], double out[M])`, and with `gradients` two
+    /// more arrays, `double grad_x[N], double grad_params[P]`, to write the
+    /// gradients into. A `Program<f32>` uses `float` and the `f` functions
+    /// of `<math.h>`.
+    pub fn to_c(&self, name: &str, gradients: bool) -> Result<String, Error<T>> {
+        self._generate(Lang::C, name, gradients)
+    }
+
+    fn _generate(&self, lang: Lang, name: &str, gradients: bool) -> Result<String, Error<T>> {
+        let (n, m, p) = (self.inputs.len(), self.outputs.len(), self.params.len());
+        let float = lang.float::<T>();
+        let mut out = String::new();
+        let mut line = |indent: usize, s: String| {
+            out.push_str(&"    ".repeat(indent));
+            out.push_str(&s);
+            out.push('\n');
+        };
+
+        let count = |k: usize, what: &str| match k {
+            1 => format!("1 {}", what),
+            k => format!("{} {}s", k, what),
+        };
+        let summary = format!(
+            "Generated by microrunn from a program with {}, {} and {}{}.",
+            count(n, "input"),
+            count(p, "param"),
+            count(m, "output"),
+            if gradients {
+                "; the gradients are those of the sum of the outputs"
+            } else {
+                ""
+            }
+        );
+        match lang {
+            Lang::Rust => {
+                line(0, format!("/// {}", summary));
+                let ret = if gradients {
+                    format!("([{f}; {}], [{f}; {}], [{f}; {}])", m, n, p, f = float)
+                } else {
+                    format!("[{}; {}]", float, m)
+                };
+                line(
+                    0,
+                    format!("pub fn {}(x: &[{}; {}]) -> {} {{", name, float, n, ret),
+                );
+            }
+            Lang::C => {
+                line(0, format!("/* {} */", summary));
+                line(0, "#include <math.h>".to_string());
+                line(0, String::new());
+                let grads = if gradients {
+                    format!(
+                        ", {f} grad_x[{}], {f} grad_params[{}]",
+                        n,
+                        p.max(1),
+                        f = float
+                    )
+                } else {
+                    String::new()
+                };
+                line(
+                    0,
+                    format!(
+                        "void {}(const {f} x[{}], {f} out[{}]{})",
+                        name,
+                        n.max(1),
+                        m.max(1),
+                        grads,
+                        f = float
+                    ),
+                );
+                line(0, "{".to_string());
+            }
+        }
+        // Without params there is nothing to declare, and an unused or
+        // empty array would not compile cleanly.
+        if p > 0 {
+            line(
+                1,
+                match lang {
+                    Lang::Rust => format!("const PARAMS: [{}; {}] = [", float, p),
+                    Lang::C => format!("static const {} params[{}] = {{", float, p),
+                },
+            );
+            for &slot in self.params.iter() {
+                line(2, format!("{},", lang.lit(self.data[slot])));
+            }
+            line(1, if lang == Lang::Rust { "];" } else { "};" }.to_string());
+            line(0, String::new());
+        }
+
+        let v = |i: usize| format!("v{}", i);
+        for (i, instr) in self.code.iter().enumerate() {
+            let a = instr.args.first().map_or(String::new(), |&a| v(a));
+            let b = instr.args.get(1).map_or(String::new(), |&b| v(b));
+
+            let expr = match &instr.op {
+                Op::None => match (
+                    self.inputs.iter().position(|&s| s == i),
+                    self.params.iter().position(|&s| s == i),
+                ) {
+                    (Some(k), _) => format!("x[{}]", k),
+                    (None, Some(k)) => lang.param(k),
+                    (None, None) => lang.lit(self.data[i]),
+                },
+                Op::Add => format!("{} + {}", a, b),
+                Op::Sub => format!("{} - {}", a, b),
+                Op::Mul => format!("{} * {}", a, b),
+                Op::Div => format!("{} / {}", a, b),
+                Op::Neg => format!("-{}", a),
+                Op::Powf(n) => lang.powf::<T>(&a, &lang.lit(*n)),
+                Op::Tanh => lang.call::<T>("tanh", &a),
+                Op::Exp => lang.call::<T>("exp", &a),
+                Op::Ln => lang.call::<T>("ln", &a),
+                Op::Relu => lang.max0::<T>(&a),
+                Op::LeakyRelu(alpha) => lang.select(
+                    &format!("{} > {}", a, lang.lit(T::zero())),
+                    &a,
+                    &format!("{} * {}", lang.lit(*alpha), a),
+                ),
+                Op::Sigmoid => {
+                    let one = lang.lit(T::one());
+                    let neg = match lang {
+                        Lang::Rust => format!("(-{})", a),
+                        Lang::C => format!("-{}", a),
+                    };
+                    let exp = lang.call::<T>("exp", &neg);
+                    format!("{} / ({} + {})", one, one, exp)
+                }
+                Op::Sqrt => lang.call::<T>("sqrt", &a),
+                Op::Abs => lang.call::<T>("abs", &a),
+                Op::Sin => lang.call::<T>("sin", &a),
+                Op::Cos => lang.call::<T>("cos", &a),
+                Op::Custom(f) => {
+                    return Err(Error::InvalidOp(format!(
+                        "cannot generate code for custom op {:?}",
+                        f
+                    )))
+                }
+            };
+            line(1, lang.assign(&v(i), float, &expr));
+        }
+
+        if gradients {
+            line(0, String::new());
+            line(
+                1,
+                match lang {
+                    Lang::Rust => format!(
+                        "let mut g = [{}; {}];",
+                        lang.lit(T::zero()),
+                        self.code.len()
+                    ),
+                    Lang::C => format!("{} g[{}] = {{0}};", float, self.code.len()),
+                },
+            );
+            for &slot in self.outputs.iter() {
+                line(1, format!("g[{}] += {};", slot, lang.lit(T::one())));
+            }
+
+            for (i, instr) in self.code.iter().enumerate().rev() {
+                let (a, b) = match instr.args[..] {
+                    [] => continue,
+                    [a] => (a, None),
+                    [a, b, ..] => (a, Some(b)),
+                };
+                let (x, y, z) = (v(a), b.map_or(String::new(), v), v(i));
+                let (one, zero) = (lang.lit(T::one()), lang.lit(T::zero()));
+                let positive = format!("{} > {}", x, zero);
+                let g = format!("g[{}]", i);
+
+                // The partial derivatives as `Op::partials` computes them,
+                // `None` where multiplying by them would change nothing.
+                let (dx, dy): (Option<String>, Option<String>) = match &instr.op {
+                    Op::Add => (None, None),
+                    Op::Sub => (None, Some(format!("-{}", one))),
+                    Op::Mul => (Some(y.clone()), Some(x.clone())),
+                    Op::Div => (
+                        Some(format!("{} / {}", one, y)),
+                        Some(format!("-{} / {}", x, lang.square(&y))),
+                    ),
+                    Op::Neg => (Some(format!("-{}", one)), None),
+                    Op::Powf(n) => (
+                        Some(format!(
+                            "{} * {}",
+                            lang.lit(*n),
+                            lang.powf::<T>(&x, &lang.lit(*n - T::one()))
+                        )),
+                        None,
+                    ),
+                    Op::Tanh => (Some(format!("{} - {}", one, lang.square(&z))), None),
+                    Op::Exp => (Some(z.clone()), None),
+                    Op::Ln => (Some(format!("{} / {}", one, x)), None),
+                    Op::Relu => (Some(lang.select(&positive, &one, &zero)), None),
+                    Op::LeakyRelu(alpha) => {
+                        (Some(lang.select(&positive, &one, &lang.lit(*alpha))), None)
+                    }
+                    Op::Sigmoid => (Some(format!("{} * ({} - {})", z, one, z)), None),
+                    Op::Sqrt => (Some(format!("{} / {}", lang.lit(lit::<T>(0.5)), z)), None),
+                    Op::Abs => {
+                        let negative = format!("{} < {}", x, zero);
+                        let sign = lang.select(
+                            &positive,
+                            &one,
+                            &lang.select(&negative, &format!("-{}", one), &zero),
+                        );
+                        (Some(sign), None)
+                    }
+                    Op::Sin => (Some(lang.call::<T>("cos", &x)), None),
+                    Op::Cos => (Some(format!("-{}", lang.call::<T>("sin", &x))), None),
+                    Op::Custom(_) | Op::None => unreachable!("checked by the forward pass"),
+                };
+
+                let term = |d: Option<String>| match d {
+                    Some(d) if d == format!("-{}", one) => format!("-{}", g),
+                    Some(d) if !d.contains(' ') => format!("{} * {}", d, g),
+                    Some(d) => format!("({}) * {}", d, g),
+                    None => g.clone(),
+                };
+                line(1, format!("g[{}] += {};", a, term(dx)));
+                if let Some(b) = b {
+                    line(1, format!("g[{}] += {};", b, term(dy)));
+                }
+            }
+        }
+
+        line(0, String::new());
+        let at = |slots: &[usize], f: &dyn Fn(usize) -> String| {
+            slots.iter().map(|&s| f(s)).collect::<Vec<String>>()
+        };
+        let (outputs, grad_x, grad_params) = (
+            at(&self.outputs, &v),
+            at(&self.inputs, &|s| format!("g[{}]", s)),
+            at(&self.params, &|s| format!("g[{}]", s)),
+        );
+        match lang {
+            Lang::Rust if gradients => line(
+                1,
+                format!(
+                    "([{}], [{}], [{}])",
+                    outputs.join(", "),
+                    grad_x.join(", "),
+                    grad_params.join(", ")
+                ),
+            ),
+            Lang::Rust => line(1, format!("[{}]", outputs.join(", "))),
+            Lang::C => {
+                let mut store = |array: &str, values: &[String]| {
+                    for (k, value) in values.iter().enumerate() {
+                        line(1, format!("{}[{}] = {};", array, k, value));
+                    }
+                };
+                store("out", &outputs);
+                if gradients {
+                    store("grad_x", &grad_x);
+                    store("grad_params", &grad_params);
+                    if p == 0 {
+                        line(1, "(void)grad_params;".to_string());
+                    }
+                }
+            }
+        }
+        line(0, "}".to_string());
+
+        Ok(out)
+    }
+}
+
+impl Lang {
+    fn float<T: Float>(self) -> &'static str {
+        match (self, Lang::is_f32::<T>()) {
+            (Lang::Rust, false) => "f64",
+            (Lang::Rust, true) => "f32",
+            (Lang::C, false) => "double",
+            (Lang::C, true) => "float",
+        }
+    }
+
+    fn is_f32<T: Float>() -> bool {
+        type_name::<T>() == "f32"
+    }
+
+    /// `x` as a literal that reads back as exactly `x`. A negative one
+    /// starts with `-`, which only ever multiplies or is an operand by
+    /// itself, so it needs no brackets.
+    fn lit<T: Float>(self, x: T) -> String {
+        let s = match self {
+            _ if x.is_nan() => return self.constant::<T>("NAN"),
+            _ if x.is_infinite() => self.constant::<T>("INFINITY"),
+            Lang::C if Lang::is_f32::<T>() => format!("{:?}f", x.abs()),
+            _ => format!("{:?}", x.abs()),
+        };
+
+        if x.is_sign_negative() {
+            format!("-{}", s)
+        } else {
+            s
+        }
+    }
+
+    fn constant<T: Float>(self, name: &str) -> String {
+        match self {
+            Lang::Rust => format!("{}::{}", self.float::<T>(), name),
+            Lang::C => name.to_string(),
+        }
+    }
+
+    fn param(self, k: usize) -> String {
+        match self {
+            Lang::Rust => format!("PARAMS[{}]", k),
+            Lang::C => format!("params[{}]", k),
+        }
+    }
+
+    fn assign(self, var: &str, float: &str, expr: &str) -> String {
+        match self {
+            Lang::Rust => format!("let {}: {} = {};", var, float, expr),
+            Lang::C => format!("const {} {} = {};", float, var, expr),
+        }
+    }
+
+    /// A one-argument function of `<math.h>` or method of the float types,
+    /// by its Rust name.
+    fn call<T: Float>(self, f: &str, x: &str) -> String {
+        match self {
+            Lang::Rust => format!("{}.{}()", x, f),
+            Lang::C => {
+                let f = match f {
+                    "ln" => "log",
+                    "abs" => "fabs",
+                    f => f,
+                };
+                format!("{}{}({})", f, Lang::suffix::<T>(), x)
+            }
+        }
+    }
+
+    fn powf<T: Float>(self, x: &str, n: &str) -> String {
+        match self {
+            Lang::Rust => format!("{}.powf({})", x, n),
+            Lang::C => format!("pow{}({}, {})", Lang::suffix::<T>(), x, n),
+        }
+    }
+
+    /// `x.powi(2)`, which is `x * x`.
+    fn square(self, x: &str) -> String {
+        match self {
+            Lang::Rust => format!("{}.powi(2)", x),
+            Lang::C => format!("({} * {})", x, x),
+        }
+    }
+
+    fn max0<T: Float>(self, x: &str) -> String {
+        let zero = self.lit(T::zero());
+        match self {
+            Lang::Rust => format!("{}.max({})", x, zero),
+            Lang::C => format!("fmax{}({}, {})", Lang::suffix::<T>(), x, zero),
+        }
+    }
+
+    fn select(self, cond: &str, a: &str, b: &str) -> String {
+        match self {
+            Lang::Rust if b.starts_with("if ") => format!("if {} {{ {} }} else {}", cond, a, b),
+            Lang::Rust => format!("if {} {{ {} }} else {{ {} }}", cond, a, b),
+            Lang::C => format!("({} ? {} : {})", cond, a, b),
+        }
+    }
+
+    fn suffix<T: Float>() -> &'static str {
+        if Lang::is_f32::<T>() {
+            "f"
+        } else {
+            ""
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::v0;
+    use crate::engine::v1::{trace, Value};
+    use crate::engine::Function;
+    use crate::nn::v0::MLP;
+
+    mod generated {
+        include!("testdata/mlp.rs");
+        include!("testdata/ops.rs");
+        include!("testdata/consts.rs");
+    }
+
+    /// Every built-in op of a program, traced with `x` and `y` as inputs
+    /// and `w` as a param.
+    fn ops() -> Program {
+        let (x, y, w) = (Value::new(0.0), Value::new(0.0), Value::new(0.75));
+        let a = (&x * &w - &y).tanh() + (&x / &y).exp() + y.clone().abs().sqrt().ln();
+        let b = x.clone().relu() * y.clone().leaky_relu(0.1) + (&w * -2.0).sigmoid();
+        let c = x.clone().powf(3.0) + y.clone().sin() - x.clone().cos();
+
+        trace(&[a, b * c], &[x, y], &[w])
+    }
+
+    /// Constant leaves fed straight into methods, as a program that was not
+    /// optimized has them, with `x` as the input.
+    fn consts() -> Program {
+        let x = Value::new(0.0);
+        let y = Value::new(2.0).exp() * &x + Value::new(0.5).tanh();
+
+        trace(&[y], &[x], &[])
+    }
+
+    #[test]
+    fn generated_source_is_up_to_date() {
+        let m: MLP = MLP::new(2, vec![3, 1]);
+
+        assert_eq!(include_str!("testdata/mlp.rs"), m.to_rust("mlp", true));
+        assert_eq!(include_str!("testdata/mlp.c"), m.to_c("mlp", true));
+        assert_eq!(
+            include_str!("testdata/ops.rs"),
+            ops().to_rust("ops", true).unwrap()
+        );
+        assert_eq!(
+            include_str!("testdata/consts.rs"),
+            consts().to_rust("consts", true).unwrap()
+        );
+    }
+    #[test]
+    fn generated_mlp_matches_call() {
+        let m: MLP = MLP::new(2, vec![3, 1]);
+        let mut program = m.compile();
+
+        for x in [[0.5, -1.0], [2.0, 0.25], [-3.0, 0.0]] {
+            let out = m.call(&[v0::Value::new(x[0]), v0::Value::new(x[1])]);
+            program.forward(&x);
+            program.backward();
+
+            let (y, grad_x, grad_params) = generated::mlp(&x);
+            assert_eq!(out[0].data, y[0]);
+            assert_eq!(program.input_grads(), grad_x);
+            assert_eq!(program.param_grads(), grad_params);
+        }
+    }
+    #[test]
+    fn compiled_c_mlp_matches_call() {
+        let dir = std::env::temp_dir().join(format!("microrunn-cc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let driver = dir.join("main.c");
+        let binary = dir.join("mlp");
+        std::fs::write(
+            &driver,
+            concat!(
+                "#include <stdio.h>\n",
+                "#include <stdlib.h>\n",
+                "#include \"",
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/engine/testdata/mlp.c\"\n",
+                "int main(int argc, char **argv)\n",
+                "{\n",
+                "    double x[2] = {atof(argv[1]), atof(argv[2])};\n",
+                "    double out[1], grad_x[2], grad_params[13];\n",
+                "    mlp(x, out, grad_x, grad_params);\n",
+                "    printf(\"%.17g %.17g %.17g\", out[0], grad_x[0], grad_x[1]);\n",
+                "    for (int i = 0; i < 13; i++)\n",
+                "        printf(\" %.17g\", grad_params[i]);\n",
+                "    return 0;\n",
+                "}\n",
+            ),
+        )
+        .unwrap();
+
+        let cc = std::process::Command::new("cc")
+            .arg(&driver)
+            .arg("-o")
+            .arg(&binary)
+            .arg("-lm")
+            .status();
+        let Ok(cc) = cc else {
+            eprintln!("skipping: no C compiler found as `cc`");
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        };
+        assert!(cc.success());
+
+        let m: MLP = MLP::new(2, vec![3, 1]);
+        let mut program = m.compile();
+        for x in [[0.5, -1.0], [2.0, 0.25], [-3.0, 0.0]] {
+            let run = std::process::Command::new(&binary)
+                .args(x.map(|x| format!("{:?}", x)))
+                .output()
+                .unwrap();
+            let got: Vec<f64> = String::from_utf8(run.stdout)
+                .unwrap()
+                .split_whitespace()
+                .map(|x| x.parse().unwrap())
+                .collect();
+
+            let out = m.call(&[v0::Value::new(x[0]), v0::Value::new(x[1])]);
+            program.forward(&x);
+            program.backward();
+            let mut expected = vec![out[0].data];
+            expected.extend(program.input_grads());
+            expected.extend(program.param_grads());
+
+            assert_eq!(expected.len(), got.len());
+            for (e, g) in expected.iter().zip(got) {
+                assert!((e - g).abs() <= 1e-12 * e.abs().max(1.0), "{} != {}", e, g);
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn generated_ops_match_program() {
+        let mut program = ops();
+
+        for x in [[0.5, -1.0], [2.0, 0.25], [-3.0, 1.5]] {
+            program.forward(&x);
+            program.backward();
+
+            let (y, grad_x, grad_params) = generated::ops(&x);
+            assert_eq!(program.outputs(), y);
+            assert_eq!(program.input_grads(), grad_x);
+            assert_eq!(program.param_grads(), grad_params);
+        }
+    }
+    #[test]
+    fn generated_consts_match_program() {
+        let mut program = consts();
+
+        for x in [[0.5], [-3.0]] {
+            program.forward(&x);
+            program.backward();
+
+            let (y, grad_x, grad_params) = generated::consts(&x);
+            assert_eq!(program.outputs(), y);
+            assert_eq!(program.input_grads(), grad_x);
+            assert_eq!(program.param_grads(), grad_params);
+        }
+    }
+    #[test]
+    fn forward_only_and_f32_signatures() {
+        let x: Value<f32> = Value::new(1.0);
+        let program = trace(&[x.clone().tanh()], &[x], &[]);
+
+        let rust = program.to_rust("f", false).unwrap();
+        let c = program.to_c("f", false).unwrap();
+
+        assert!(rust.contains("pub fn f(x: &[f32; 1]) -> [f32; 1] {"));
+        assert!(rust.contains("let v1: f32 = v0.tanh();"));
+        assert!(c.contains("void f(const float x[1], float out[1])"));
+        assert!(c.contains("const float v1 = tanhf(v0);"));
+        assert!(!c.contains("g["));
+    }
+    #[test]
+    fn custom_ops_cannot_be_generated() {
+        #[derive(Debug)]
+        struct Square;
+
+        impl Function for Square {
+            fn forward(&self, inputs: &[f64]) -> f64 {
+                inputs[0] * inputs[0]
+            }
+
+            fn backward(&self, inputs: &[f64], _output: f64, grad: f64) -> Vec<f64> {
+                vec![2.0 * inputs[0] * grad]
+            }
+        }
+        let x = Value::new(1.0);
+        let program = trace(&[Value::apply(Square, vec![x.clone()])], &[x], &[]);
+
+        assert!(matches!(
+            program.to_c("square", false),
+            Err(Error::InvalidOp(_))
+        ));
+    }
+}
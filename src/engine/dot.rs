@@ -0,0 +1,91 @@
+use super::Float;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// One node of a graph being exported, keyed by an engine-specific `id`.
+pub(crate) struct DotNode<T> {
+    pub id: usize,
+    pub op: Option<String>,
+    pub data: T,
+    pub grad: T,
+}
+
+/// Renders nodes as Graphviz records and `edges` as `(from, to)` pairs of
+/// node ids, with data flowing left to right.
+pub(crate) fn render<T: Float>(nodes: &[DotNode<T>], edges: &[(usize, usize)]) -> String {
+    let names: HashMap<usize, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.id, i))
+        .collect();
+    let mut out = String::new();
+
+    writeln!(out, "digraph {{").unwrap();
+    writeln!(out, "  rankdir=LR;").unwrap();
+    writeln!(out, "  node [shape=record];").unwrap();
+
+    for (i, node) in nodes.iter().enumerate() {
+        let op = match &node.op {
+            Some(op) => format!("{} | ", escape(op)),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            "  n{} [label=\"{{ {}data {:.4?} | grad {:.4?} }}\"];",
+            i, op, node.data, node.grad
+        )
+        .unwrap();
+    }
+
+    for (from, to) in edges.iter() {
+        writeln!(out, "  n{} -> n{};", names[from], names[to]).unwrap();
+    }
+
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+fn escape(label: &str) -> String {
+    label
+        .chars()
+        .flat_map(|c| match c {
+            '{' | '}' | '|' | '<' | '>' | '"' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_records_and_edges() {
+        let nodes = vec![
+            DotNode {
+                id: 7,
+                op: None,
+                data: 2.0,
+                grad: 0.5,
+            },
+            DotNode {
+                id: 3,
+                op: Some("Powf(2.0)".to_string()),
+                data: 4.0,
+                grad: 1.0,
+            },
+        ];
+
+        let dot = render(&nodes, &[(7, 3)]);
+
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains("n0 [label=\"{ data 2.0000 | grad 0.5000 }\"];"));
+        assert!(dot.contains("n1 [label=\"{ Powf(2.0) | data 4.0000 | grad 1.0000 }\"];"));
+        assert!(dot.contains("n0 -> n1;"));
+    }
+    #[test]
+    fn escape_record_characters() {
+        assert_eq!("a\\|b\\{\\}", escape("a|b{}"));
+    }
+}
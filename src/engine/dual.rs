@@ -0,0 +1,246 @@
+use super::{lit, sign, Float};
+use crate::error::{check_len, Error};
+use std::iter::Sum;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A dual number `data + tangent * e` with `e^2 = 0`. Evaluating a function
+/// on duals carries the directional derivative along with the value, which
+/// is forward-mode differentiation: one pass per input direction and no
+/// graph at all.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dual<T = f64> {
+    pub data: T,
+    pub tangent: T,
+}
+
+impl<T: Float> Dual<T> {
+    pub fn new(data: T, tangent: T) -> Dual<T> {
+        Dual { data, tangent }
+    }
+
+    /// A value that does not vary along the differentiation direction.
+    pub fn constant(data: T) -> Dual<T> {
+        Dual::new(data, T::zero())
+    }
+
+    /// A value with unit tangent, i.e. the variable being differentiated.
+    pub fn variable(data: T) -> Dual<T> {
+        Dual::new(data, T::one())
+    }
+
+    pub fn tanh(self) -> Dual<T> {
+        let data = self.data.tanh();
+        Dual::new(data, (T::one() - data.powi(2)) * self.tangent)
+    }
+
+    pub fn powf(self, n: T) -> Dual<T> {
+        Dual::new(
+            self.data.powf(n),
+            n * self.data.powf(n - T::one()) * self.tangent,
+        )
+    }
+
+    pub fn exp(self) -> Dual<T> {
+        let data = self.data.exp();
+        Dual::new(data, data * self.tangent)
+    }
+
+    pub fn ln(self) -> Dual<T> {
+        Dual::new(self.data.ln(), self.tangent / self.data)
+    }
+
+    pub fn relu(self) -> Dual<T> {
+        if self.data > T::zero() {
+            self
+        } else {
+            Dual::constant(T::zero())
+        }
+    }
+
+    pub fn leaky_relu(self, alpha: T) -> Dual<T> {
+        if self.data > T::zero() {
+            self
+        } else {
+            Dual::new(alpha * self.data, alpha * self.tangent)
+        }
+    }
+
+    pub fn sigmoid(self) -> Dual<T> {
+        let data = T::one() / (T::one() + (-self.data).exp());
+        Dual::new(data, data * (T::one() - data) * self.tangent)
+    }
+
+    pub fn sqrt(self) -> Dual<T> {
+        let data = self.data.sqrt();
+        Dual::new(data, lit::<T>(0.5) / data * self.tangent)
+    }
+
+    pub fn abs(self) -> Dual<T> {
+        Dual::new(self.data.abs(), sign(self.data) * self.tangent)
+    }
+
+    pub fn sin(self) -> Dual<T> {
+        Dual::new(self.data.sin(), self.data.cos() * self.tangent)
+    }
+
+    pub fn cos(self) -> Dual<T> {
+        Dual::new(self.data.cos(), -self.data.sin() * self.tangent)
+    }
+}
+
+/// Jacobian-vector product: evaluates `f` at `x` and differentiates it along
+/// `v` in a single forward pass. Returns the outputs of `f` and `J(x) v`.
+pub fn jvp<T, F>(f: F, x: &[T], v: &[T]) -> (Vec<T>, Vec<T>)
+where
+    T: Float,
+    F: Fn(&[Dual<T>]) -> Vec<Dual<T>>,
+{
+    try_jvp(f, x, v).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Like `jvp`, failing unless `v` has one entry per entry of `x`.
+pub fn try_jvp<T, F>(f: F, x: &[T], v: &[T]) -> Result<(Vec<T>, Vec<T>), Error<T>>
+where
+    T: Float,
+    F: Fn(&[Dual<T>]) -> Vec<Dual<T>>,
+{
+    check_len(x.len(), v.len())?;
+
+    let inputs: Vec<Dual<T>> = x
+        .iter()
+        .zip(v.iter())
+        .map(|(&x, &v)| Dual::new(x, v))
+        .collect();
+
+    Ok(f(&inputs).into_iter().map(|d| (d.data, d.tangent)).unzip())
+}
+
+impl<T: Float> Add for Dual<T> {
+    type Output = Dual<T>;
+
+    fn add(self, other: Self) -> Self::Output {
+        Dual::new(self.data + other.data, self.tangent + other.tangent)
+    }
+}
+
+impl<T: Float> Sub for Dual<T> {
+    type Output = Dual<T>;
+
+    fn sub(self, other: Self) -> Self::Output {
+        Dual::new(self.data - other.data, self.tangent - other.tangent)
+    }
+}
+
+impl<T: Float> Mul for Dual<T> {
+    type Output = Dual<T>;
+
+    fn mul(self, other: Self) -> Self::Output {
+        Dual::new(
+            self.data * other.data,
+            self.tangent * other.data + self.data * other.tangent,
+        )
+    }
+}
+
+impl<T: Float> Div for Dual<T> {
+    type Output = Dual<T>;
+
+    fn div(self, other: Self) -> Self::Output {
+        Dual::new(
+            self.data / other.data,
+            (self.tangent * other.data - self.data * other.tangent) / other.data.powi(2),
+        )
+    }
+}
+
+impl<T: Float> Neg for Dual<T> {
+    type Output = Dual<T>;
+
+    fn neg(self) -> Self::Output {
+        Dual::new(-self.data, -self.tangent)
+    }
+}
+
+impl<T: Float> Add<T> for Dual<T> {
+    type Output = Dual<T>;
+
+    fn add(self, other: T) -> Self::Output {
+        self + Dual::constant(other)
+    }
+}
+
+impl<T: Float> Sub<T> for Dual<T> {
+    type Output = Dual<T>;
+
+    fn sub(self, other: T) -> Self::Output {
+        self - Dual::constant(other)
+    }
+}
+
+impl<T: Float> Mul<T> for Dual<T> {
+    type Output = Dual<T>;
+
+    fn mul(self, other: T) -> Self::Output {
+        self * Dual::constant(other)
+    }
+}
+
+impl<T: Float> Div<T> for Dual<T> {
+    type Output = Dual<T>;
+
+    fn div(self, other: T) -> Self::Output {
+        self / Dual::constant(other)
+    }
+}
+
+impl<T: Float> Sum for Dual<T> {
+    fn sum<I: Iterator<Item = Dual<T>>>(iter: I) -> Dual<T> {
+        iter.fold(Dual::constant(T::zero()), |a, b| a + b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn product_rule() {
+        let x = Dual::variable(3.0);
+        let y = x * x + x * 2.0;
+
+        assert_eq!(15.0, y.data);
+        assert_eq!(8.0, y.tangent);
+    }
+    #[test]
+    fn quotient_and_chain_rule() {
+        let x = Dual::variable(0.5);
+        let y = x.sin() / x.exp();
+
+        let expected = (0.5_f64.cos() - 0.5_f64.sin()) / 0.5_f64.exp();
+        assert!((expected - y.tangent).abs() < 1e-12);
+    }
+    #[test]
+    fn jvp_of_vector_function() {
+        let f = |x: &[Dual]| vec![x[0] * x[1], x[0].tanh() + x[1].powf(2.0)];
+
+        let (out, tangent) = jvp(f, &[2.0, 3.0], &[1.0, -1.0]);
+
+        assert_eq!(vec![6.0, 2.0_f64.tanh() + 9.0], out);
+        assert_eq!(3.0 - 2.0, tangent[0]);
+        assert!((1.0 - 2.0_f64.tanh().powi(2) - 6.0 - tangent[1]).abs() < 1e-12);
+    }
+    #[test]
+    fn jvp_rejects_a_direction_of_the_wrong_length() {
+        let f = |x: &[Dual]| vec![x[0] * x[1]];
+
+        let result = try_jvp(f, &[2.0, 3.0], &[1.0]);
+
+        assert!(matches!(
+            result,
+            Err(Error::LengthMismatch {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+}
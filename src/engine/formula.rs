@@ -0,0 +1,631 @@
+use super::graph::{Node, NodeId, OpKind};
+use super::{lit, Float};
+use crate::error::Error;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+/// The expression behind a node, rebuilt from its graph, for printing as
+/// plain text with `Display`, such as `tanh(w0*x0 + w1*x1 + b)`, or as LaTeX
+/// with `to_latex`. Leaves are shown by their label; those without one are
+/// taken to be constants, and folded with each other. A node used twice is
+/// written out twice, and printing recurses over the expression, so this is
+/// meant for the small graphs one reads rather than for whole models: graphs
+/// nested deeper than [`MAX_DEPTH`], or whose expression would have more
+/// than [`MAX_SIZE`] nodes once every shared node is written out, are
+/// rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Formula<T = f64> {
+    expr: Expr<T>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr<T> {
+    Leaf {
+        id: NodeId,
+        label: Option<String>,
+        data: T,
+    },
+    /// A number introduced by a derivative, or an unlabeled leaf once the
+    /// formula is printed.
+    Const(T),
+    Add(Box<Expr<T>>, Box<Expr<T>>),
+    Sub(Box<Expr<T>>, Box<Expr<T>>),
+    Mul(Box<Expr<T>>, Box<Expr<T>>),
+    Div(Box<Expr<T>>, Box<Expr<T>>),
+    Neg(Box<Expr<T>>),
+    Powf(Box<Expr<T>>, T),
+    /// Any other op, applied to its operands.
+    Call(OpKind<T>, Vec<Expr<T>>),
+    /// One where the operand is positive and zero elsewhere, written
+    /// `[x > 0]`.
+    Step(Box<Expr<T>>),
+    Sign(Box<Expr<T>>),
+}
+
+/// How many levels a formula may nest. Building, differentiating, printing
+/// and dropping a formula recurse once per level, and a derivative nests up
+/// to three levels per level of the formula it is taken of, so this keeps
+/// all of them well within the 2 MiB stack of a spawned thread.
+pub const MAX_DEPTH: usize = 64;
+
+/// How many nodes a formula may have once written out. A node used twice
+/// counts twice, so a chain of `y = &y + &y` doubles in size with every
+/// link; this stops it before it takes more memory and time than printing
+/// is worth.
+pub const MAX_SIZE: usize = 4096;
+
+impl<T: Float> Formula<T> {
+    /// The formula of the last of `nodes`, which are in topological order
+    /// as `nodes()` of `engine::v0` and `engine::v1` returns them. Fails if
+    /// there are no nodes, they nest more than [`MAX_DEPTH`] levels deep, a
+    /// leaf being one level, or the formula would have more than
+    /// [`MAX_SIZE`] nodes.
+    pub fn new(nodes: &[Node<T>]) -> Result<Formula<T>, Error<T>> {
+        let last = nodes.last().ok_or(Error::EmptyGraph)?;
+        let mut depths: HashMap<NodeId, usize> = HashMap::new();
+        let mut sizes: HashMap<NodeId, usize> = HashMap::new();
+        for node in nodes.iter() {
+            let depth = 1 + node.parents.iter().map(|p| depths[p]).max().unwrap_or(0);
+            check_depth(depth)?;
+            depths.insert(node.id, depth);
+
+            let size = node
+                .parents
+                .iter()
+                .fold(1, |size: usize, p| size.saturating_add(sizes[p]));
+            check_size(size)?;
+            sizes.insert(node.id, size);
+        }
+
+        let mut exprs: HashMap<NodeId, Expr<T>> = HashMap::new();
+        for node in nodes.iter() {
+            let args: Vec<Expr<T>> = node.parents.iter().map(|p| exprs[p].clone()).collect();
+            let arg = |i: usize| Box::new(args[i].clone());
+
+            let expr = match &node.op {
+                OpKind::Leaf => Expr::Leaf {
+                    id: node.id,
+                    label: node.label.clone(),
+                    data: node.data,
+                },
+                OpKind::Add => Expr::Add(arg(0), arg(1)),
+                OpKind::Mul => Expr::Mul(arg(0), arg(1)),
+                OpKind::Div => Expr::Div(arg(0), arg(1)),
+                OpKind::Powf(n) => Expr::Powf(arg(0), *n),
+                op => Expr::Call(op.clone(), args),
+            };
+            exprs.insert(node.id, expr);
+        }
+
+        Ok(Formula {
+            expr: exprs.remove(&last.id).unwrap(),
+        })
+    }
+
+    /// The symbolic derivative with respect to the leaf `leaf`. Any other
+    /// id, including one of a node computed from others, gives zero. Fails
+    /// on a custom op that depends on `leaf`, as its derivative has no
+    /// formula, and on a formula nested deeper than [`MAX_DEPTH`] or with
+    /// more than [`MAX_SIZE`] nodes, such as the derivative of a derivative
+    /// of a deep one.
+    pub fn derivative(&self, leaf: NodeId) -> Result<Formula<T>, Error<T>> {
+        check_depth(self.expr.depth())?;
+
+        let expr = derivative(&self.expr, leaf)?;
+        check_size(expr.size())?;
+
+        Ok(Formula { expr })
+    }
+
+    pub fn to_latex(&self) -> String {
+        let mut out = String::new();
+        write(&self.expr.simplify(true), &mut out, true);
+        out
+    }
+}
+
+impl<T: Float> Display for Formula<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        write(&self.expr.simplify(true), &mut out, false);
+        f.write_str(&out)
+    }
+}
+
+fn check_depth<T>(depth: usize) -> Result<(), Error<T>> {
+    if depth > MAX_DEPTH {
+        return Err(Error::TooDeep {
+            depth,
+            max: MAX_DEPTH,
+        });
+    }
+    Ok(())
+}
+
+fn check_size<T>(size: usize) -> Result<(), Error<T>> {
+    if size > MAX_SIZE {
+        return Err(Error::TooLarge {
+            size,
+            max: MAX_SIZE,
+        });
+    }
+    Ok(())
+}
+
+fn derivative<T: Float>(e: &Expr<T>, leaf: NodeId) -> Result<Expr<T>, Error<T>> {
+    use Expr::*;
+    let d = |e: &Expr<T>| derivative(e, leaf).map(Box::new);
+    let b = |e: &Expr<T>| Box::new(e.clone());
+    let num = |x: f64| Box::new(Const(lit(x)));
+
+    let result = match e {
+        Leaf { id, .. } if *id == leaf => Const(T::one()),
+        Leaf { .. } | Const(_) | Step(_) | Sign(_) => Const(T::zero()),
+        Add(u, v) => Add(d(u)?, d(v)?),
+        Sub(u, v) => Sub(d(u)?, d(v)?),
+        Mul(u, v) => Add(Box::new(Mul(d(u)?, b(v))), Box::new(Mul(b(u), d(v)?))),
+        Div(u, v) => match *d(v)? {
+            Const(c) if c == T::zero() => Div(d(u)?, b(v)),
+            dv => Div(
+                Box::new(Sub(
+                    Box::new(Mul(d(u)?, b(v))),
+                    Box::new(Mul(b(u), Box::new(dv))),
+                )),
+                Box::new(Powf(b(v), lit(2.0))),
+            ),
+        },
+        Neg(u) => Neg(d(u)?),
+        Powf(u, n) => Mul(
+            Box::new(Mul(
+                Box::new(Const(*n)),
+                Box::new(Powf(b(u), *n - T::one())),
+            )),
+            d(u)?,
+        ),
+        Call(OpKind::Custom(name), args) => {
+            for arg in args.iter() {
+                if derivative(arg, leaf)? != Const(T::zero()) {
+                    return Err(Error::InvalidOp(format!(
+                        "{} has no symbolic derivative",
+                        name
+                    )));
+                }
+            }
+            Const(T::zero())
+        }
+        Call(op, args) => {
+            let u = &args[0];
+            let call = |op: OpKind<T>| Box::new(Call(op, vec![u.clone()]));
+            let outer = match op {
+                OpKind::Tanh => Sub(num(1.0), Box::new(Powf(b(e), lit(2.0)))),
+                OpKind::Exp => e.clone(),
+                OpKind::Ln => Div(num(1.0), b(u)),
+                OpKind::Relu => Step(b(u)),
+                OpKind::LeakyRelu(alpha) => Add(
+                    Box::new(Step(b(u))),
+                    Box::new(Mul(
+                        Box::new(Const(*alpha)),
+                        Box::new(Sub(num(1.0), Box::new(Step(b(u))))),
+                    )),
+                ),
+                OpKind::Sigmoid => Mul(b(e), Box::new(Sub(num(1.0), b(e)))),
+                OpKind::Sqrt => Div(num(1.0), Box::new(Mul(num(2.0), b(e)))),
+                OpKind::Abs => Sign(b(u)),
+                OpKind::Sin => *call(OpKind::Cos),
+                OpKind::Cos => Neg(call(OpKind::Sin)),
+                op => unreachable!("{} is not a function", op),
+            };
+            Mul(Box::new(outer), d(u)?)
+        }
+    };
+
+    Ok(result.simplify(false))
+}
+
+impl<T: Float> Expr<T> {
+    /// The expression with zero terms, unit factors and exponents, double
+    /// negations and additions of negated terms removed and constants
+    /// folded, so that `x + y * -1` reads `x - y`. With `leaves`, unlabeled
+    /// leaves become constants first.
+    fn simplify(&self, leaves: bool) -> Expr<T> {
+        use Expr::*;
+        let s = |e: &Expr<T>| e.simplify(leaves);
+        let num = |e: &Expr<T>| match e {
+            Const(c) => Some(*c),
+            _ => None,
+        };
+        let (zero, one) = (Some(T::zero()), Some(T::one()));
+
+        match self {
+            Leaf {
+                label: None, data, ..
+            } if leaves => Const(*data),
+            Add(u, v) => match (s(u), s(v)) {
+                (Const(a), Const(b)) => Const(a + b),
+                (u, v) if num(&u) == zero => v,
+                (u, v) if num(&v) == zero => u,
+                (u, Neg(v)) => Sub(Box::new(u), v),
+                (u, v) => match num(&v) {
+                    Some(c) if c < T::zero() => Sub(Box::new(u), Box::new(Const(-c))),
+                    _ => Add(Box::new(u), Box::new(v)),
+                },
+            },
+            Sub(u, v) => match (s(u), s(v)) {
+                (Const(a), Const(b)) => Const(a - b),
+                (u, v) if num(&v) == zero => u,
+                (u, v) if num(&u) == zero => Neg(Box::new(v)).simplify(leaves),
+                (u, Neg(v)) => Add(Box::new(u), v),
+                (u, v) => Sub(Box::new(u), Box::new(v)),
+            },
+            Mul(u, v) => match (s(u), s(v)) {
+                (Const(a), Const(b)) => Const(a * b),
+                (u, v) if num(&u) == zero || num(&v) == zero => Const(T::zero()),
+                (u, v) if num(&u) == one => v,
+                (u, v) if num(&v) == one => u,
+                (u, v) if num(&u) == Some(-T::one()) => Neg(Box::new(v)).simplify(leaves),
+                (u, v) if num(&v) == Some(-T::one()) => Neg(Box::new(u)).simplify(leaves),
+                (u, v) => Mul(Box::new(u), Box::new(v)),
+            },
+            Div(u, v) => match (s(u), s(v)) {
+                (Const(a), Const(b)) => Const(a / b),
+                (u, _) if num(&u) == zero => Const(T::zero()),
+                (u, v) if num(&v) == one => u,
+                (u, v) => Div(Box::new(u), Box::new(v)),
+            },
+            Neg(u) => match s(u) {
+                Const(a) => Const(-a),
+                Neg(u) => *u,
+                u => Neg(Box::new(u)),
+            },
+            Powf(u, n) => match s(u) {
+                _ if *n == T::zero() => Const(T::one()),
+                u if *n == T::one() => u,
+                Const(a) => Const(a.powf(*n)),
+                u => Powf(Box::new(u), *n),
+            },
+            Call(op, args) => Call(op.clone(), args.iter().map(s).collect()),
+            Step(u) => Step(Box::new(s(u))),
+            Sign(u) => Sign(Box::new(s(u))),
+            e => e.clone(),
+        }
+    }
+
+    /// The number of levels from the root to the deepest leaf, counted
+    /// with an explicit stack.
+    fn depth(&self) -> usize {
+        use Expr::*;
+        let mut max = 0;
+        let mut stack: Vec<(&Expr<T>, usize)> = vec![(self, 1)];
+
+        while let Some((e, depth)) = stack.pop() {
+            max = max.max(depth);
+            match e {
+                Leaf { .. } | Const(_) => {}
+                Add(u, v) | Sub(u, v) | Mul(u, v) | Div(u, v) => {
+                    stack.push((u, depth + 1));
+                    stack.push((v, depth + 1));
+                }
+                Neg(u) | Powf(u, _) | Step(u) | Sign(u) => stack.push((u, depth + 1)),
+                Call(_, args) => stack.extend(args.iter().map(|a| (a, depth + 1))),
+            }
+        }
+
+        max
+    }
+
+    /// The number of nodes, counted with an explicit stack.
+    fn size(&self) -> usize {
+        use Expr::*;
+        let mut size = 0;
+        let mut stack: Vec<&Expr<T>> = vec![self];
+
+        while let Some(e) = stack.pop() {
+            size += 1;
+            match e {
+                Leaf { .. } | Const(_) => {}
+                Add(u, v) | Sub(u, v) | Mul(u, v) | Div(u, v) => {
+                    stack.push(u);
+                    stack.push(v);
+                }
+                Neg(u) | Powf(u, _) | Step(u) | Sign(u) => stack.push(u),
+                Call(_, args) => stack.extend(args.iter()),
+            }
+        }
+
+        size
+    }
+
+    /// How tightly the expression binds: a sum 1, a product 2, a power 3,
+    /// anything written as a name or in brackets 4. A negation binds like a
+    /// sum so that it is bracketed as an operand of a product.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Add(..) | Expr::Sub(..) | Expr::Neg(_) => 1,
+            Expr::Const(c) if *c < T::zero() => 1,
+            Expr::Mul(..) | Expr::Div(..) => 2,
+            Expr::Powf(..) => 3,
+            _ => 4,
+        }
+    }
+}
+
+/// Appends `e` to `out`, as LaTeX if `latex`.
+fn write<T: Float>(e: &Expr<T>, out: &mut String, latex: bool) {
+    let operand = |e: &Expr<T>, min: u8, out: &mut String| {
+        if e.precedence() < min {
+            out.push_str(if latex { "\\left(" } else { "(" });
+            write(e, out, latex);
+            out.push_str(if latex { "\\right)" } else { ")" });
+        } else {
+            write(e, out, latex);
+        }
+    };
+    let call = |name: &str, args: &[&Expr<T>], extra: Option<T>, out: &mut String| {
+        out.push_str(name);
+        out.push_str(if latex { "\\left(" } else { "(" });
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            write(arg, out, latex);
+        }
+        if let Some(x) = extra {
+            out.push_str(", ");
+            out.push_str(&number(x));
+        }
+        out.push_str(if latex { "\\right)" } else { ")" });
+    };
+
+    match e {
+        Expr::Leaf {
+            label: Some(label), ..
+        } => out.push_str(&if latex {
+            latex_name(label)
+        } else {
+            label.clone()
+        }),
+        Expr::Leaf { data: c, .. } | Expr::Const(c) => out.push_str(&number(*c)),
+        Expr::Add(u, v) | Expr::Sub(u, v) => {
+            operand(u, 1, out);
+            out.push_str(if let Expr::Add(..) = e { " + " } else { " - " });
+            operand(v, 2, out);
+        }
+        Expr::Mul(u, v) => {
+            operand(u, 2, out);
+            out.push_str(if latex { " \\cdot " } else { "*" });
+            operand(v, 2, out);
+        }
+        Expr::Div(u, v) if latex => {
+            out.push_str("\\frac{");
+            write(u, out, latex);
+            out.push_str("}{");
+            write(v, out, latex);
+            out.push('}');
+        }
+        Expr::Div(u, v) => {
+            operand(u, 2, out);
+            out.push('/');
+            operand(v, 3, out);
+        }
+        Expr::Neg(u) => {
+            out.push('-');
+            operand(u, 2, out);
+        }
+        Expr::Powf(u, n) => {
+            operand(u, 4, out);
+            match (latex, *n < T::zero()) {
+                (true, _) => out.push_str(&format!("^{{{}}}", number(*n))),
+                (false, true) => out.push_str(&format!("^({})", number(*n))),
+                (false, false) => out.push_str(&format!("^{}", number(*n))),
+            }
+        }
+        Expr::Call(OpKind::Sqrt, args) if latex => {
+            out.push_str("\\sqrt{");
+            write(&args[0], out, latex);
+            out.push('}');
+        }
+        Expr::Call(OpKind::Abs, args) if latex => {
+            out.push_str("\\left|");
+            write(&args[0], out, latex);
+            out.push_str("\\right|");
+        }
+        Expr::Call(op, args) => {
+            let args: Vec<&Expr<T>> = args.iter().collect();
+            let (name, extra) = match op {
+                OpKind::LeakyRelu(alpha) => ("leaky_relu".to_string(), Some(*alpha)),
+                OpKind::Custom(name) => (name.clone(), None),
+                op => (format!("{:?}", op).to_lowercase(), None),
+            };
+            let name = match (latex, op) {
+                (false, _) => name,
+                (true, OpKind::Tanh | OpKind::Exp | OpKind::Ln | OpKind::Sin | OpKind::Cos) => {
+                    format!("\\{}", name)
+                }
+                (true, OpKind::Sigmoid) => "\\sigma".to_string(),
+                (true, _) => format!("\\operatorname{{{}}}", name.replace('_', "\\_")),
+            };
+            call(&name, &args, extra, out);
+        }
+        Expr::Step(u) => {
+            out.push('[');
+            write(u, out, latex);
+            out.push_str(" > 0]");
+        }
+        Expr::Sign(u) => {
+            let name = if latex {
+                "\\operatorname{sign}"
+            } else {
+                "sign"
+            };
+            call(name, &[u], None, out);
+        }
+    }
+}
+
+/// `x` without a trailing `.0`, so that `2.0` prints as `2`.
+fn number<T: Float>(x: T) -> String {
+    let s = format!("{:?}", x);
+    match s.strip_suffix(".0") {
+        Some(s) => s.to_string(),
+        None => s,
+    }
+}
+
+/// A label such as `w0` with its trailing digits as a subscript, `w_{0}`.
+fn latex_name(label: &str) -> String {
+    let name = label.trim_end_matches(|c: char| c.is_ascii_digit());
+    if name.is_empty() || name.len() == label.len() {
+        label.to_string()
+    } else {
+        format!("{}_{{{}}}", name, &label[name.len()..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::v1::Value;
+
+    fn labelled(data: f64, label: &str) -> Value {
+        let v = Value::new(data);
+        v.set_label(label);
+        v
+    }
+
+    #[test]
+    fn neuron_prints_as_text_and_latex() {
+        let (x0, x1) = (labelled(2.0, "x0"), labelled(0.0, "x1"));
+        let (w0, w1, b) = (
+            labelled(-3.0, "w0"),
+            labelled(1.0, "w1"),
+            labelled(6.9, "b"),
+        );
+        let n = (&w0 * &x0 + &w1 * &x1 + &b).tanh();
+
+        assert_eq!("tanh(w0*x0 + w1*x1 + b)", n.formula().unwrap().to_string());
+        assert_eq!(
+            "\\tanh\\left(w_{0} \\cdot x_{0} + w_{1} \\cdot x_{1} + b\\right)",
+            n.formula().unwrap().to_latex()
+        );
+    }
+    #[test]
+    fn derivative_with_respect_to_a_leaf() {
+        let (x, w, b) = (labelled(2.0, "x"), labelled(-3.0, "w"), labelled(1.0, "b"));
+        let y = (&w * &x + &b).tanh();
+
+        let dw = y.formula().unwrap().derivative(w.id()).unwrap();
+
+        assert_eq!("(1 - tanh(w*x + b)^2)*x", dw.to_string());
+        assert_eq!(
+            "\\left(1 - \\tanh\\left(w \\cdot x + b\\right)^{2}\\right) \\cdot x",
+            dw.to_latex()
+        );
+        assert_eq!(
+            "0",
+            y.formula()
+                .unwrap()
+                .derivative(Value::new(1.0).id())
+                .unwrap()
+                .to_string()
+        );
+    }
+    #[test]
+    fn unlabeled_leaves_print_as_numbers() {
+        let x = labelled(3.0, "x");
+        let y = (x.clone() - Value::new(1.5)).powf(2.0) / 2.0 - x.clone() * -1.0;
+
+        assert_eq!("(x - 1.5)^2/2 + x", y.formula().unwrap().to_string());
+        assert_eq!(
+            "\\frac{\\left(x - 1.5\\right)^{2}}{2} + x",
+            y.formula().unwrap().to_latex()
+        );
+        assert_eq!(
+            "2*(x - 1.5)/2 + 1",
+            y.formula().unwrap().derivative(x.id()).unwrap().to_string()
+        );
+    }
+    #[test]
+    fn derivatives_of_every_function() {
+        let x = labelled(0.5, "x");
+        let d = |y: Value| y.formula().unwrap().derivative(x.id()).unwrap().to_string();
+
+        assert_eq!("exp(x)", d(x.clone().exp()));
+        assert_eq!("1/x", d(x.clone().ln()));
+        assert_eq!("[x > 0]", d(x.clone().relu()));
+        assert_eq!("sigmoid(x)*(1 - sigmoid(x))", d(x.clone().sigmoid()));
+        assert_eq!("1/(2*sqrt(x))", d(x.clone().sqrt()));
+        assert_eq!("sign(x)", d(x.clone().abs()));
+        assert_eq!("-sin(x)", d(x.clone().cos()));
+        assert_eq!("cos(x*2)*2", d((x.clone() * 2.0).sin()));
+    }
+    #[test]
+    fn custom_ops_have_no_symbolic_derivative() {
+        #[derive(Debug)]
+        struct Softplus;
+
+        impl crate::engine::Function for Softplus {
+            fn forward(&self, inputs: &[f64]) -> f64 {
+                inputs[0].exp().ln_1p()
+            }
+
+            fn backward(&self, inputs: &[f64], _output: f64, grad: f64) -> Vec<f64> {
+                vec![grad / (1.0 + (-inputs[0]).exp())]
+            }
+        }
+        let (x, c) = (labelled(0.5, "x"), labelled(1.0, "c"));
+        let y = Value::apply(Softplus, vec![c.clone()]) * &x;
+
+        assert_eq!("Softplus(c)*x", y.formula().unwrap().to_string());
+        assert_eq!(
+            "Softplus(c)",
+            y.formula().unwrap().derivative(x.id()).unwrap().to_string()
+        );
+        assert!(matches!(
+            y.formula().unwrap().derivative(c.id()),
+            Err(Error::InvalidOp(_))
+        ));
+    }
+    #[test]
+    fn deep_graphs_are_rejected_instead_of_overflowing() {
+        let x = labelled(2.0, "x");
+        let chain = |ops: usize| (0..ops).fold(x.clone(), |y, _| (&y / &x).tanh());
+
+        let deepest = chain((MAX_DEPTH - 1) / 2).formula().unwrap();
+        let dx = deepest.derivative(x.id()).unwrap();
+        assert!(dx.to_string().starts_with("(1 - tanh("));
+        assert!(dx.to_latex().len() > dx.to_string().len());
+
+        assert_eq!(
+            Err(Error::TooDeep {
+                depth: MAX_DEPTH + 1,
+                max: MAX_DEPTH
+            }),
+            chain(MAX_DEPTH / 2).formula()
+        );
+        assert_eq!(Err(Error::EmptyGraph), Formula::<f64>::new(&[]));
+    }
+    #[test]
+    fn shared_nodes_are_rejected_before_they_blow_up() {
+        let x = labelled(2.0, "x");
+        let chain = |links: usize| (0..links).fold(x.clone(), |y, _| &y + &y);
+
+        let largest = chain(11).formula().unwrap();
+        assert_eq!(4095, largest.expr.size());
+        assert_eq!("2048", largest.derivative(x.id()).unwrap().to_string());
+
+        assert_eq!(
+            Err(Error::TooLarge {
+                size: 8191,
+                max: MAX_SIZE
+            }),
+            chain(12).formula()
+        );
+        assert!(matches!(chain(40).formula(), Err(Error::TooLarge { .. })));
+
+        let power = (0..10).fold(x.clone(), |y, _| &y * &y).formula().unwrap();
+        assert!(matches!(
+            power.derivative(x.id()),
+            Err(Error::TooLarge { .. })
+        ));
+    }
+}
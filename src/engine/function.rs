@@ -0,0 +1,19 @@
+use std::fmt::Debug;
+
+/// A differentiable op on scalars. Implementing it adds an op to
+/// `engine::v1` graphs without touching the engine: attach it with
+/// `engine::v1::Value::apply` and `backward`, `gradients`, `gradcheck` and
+/// `to_dot` pick it up like any built-in op, which are themselves
+/// implemented through this trait. Its `Debug` output names the op in
+/// graph dumps and gradcheck reports.
+pub trait Function<T = f64>: Debug {
+    /// Output data of the op applied to `inputs`.
+    fn forward(&self, inputs: &[T]) -> T;
+
+    /// Maps the upstream gradient `grad` to one gradient per input, given
+    /// the inputs and the `output` that `forward` produced from them. The
+    /// result must be linear in `grad`; `Value::gradients` relies on this
+    /// and treats the local derivatives of a custom op as constants, so
+    /// only first derivatives through it are exact.
+    fn backward(&self, inputs: &[T], output: T, grad: T) -> Vec<T>;
+}
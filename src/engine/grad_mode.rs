@@ -0,0 +1,52 @@
+use std::cell::Cell;
+
+thread_local! {
+    static GRAD_ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Restores the previous grad mode of the current thread when dropped.
+#[must_use = "grad tracking is re-enabled as soon as the guard is dropped"]
+pub struct NoGradGuard {
+    prev: bool,
+}
+
+/// Turns off graph construction on the current thread until the returned
+/// guard is dropped. While it is alive, ops on `engine::v0`, `engine::v1`
+/// and `engine::sync` values and on `engine::tensor` tensors only compute
+/// `data` and return leaves with no `_prev`, so inference pays nothing for
+/// autograd. Guards nest.
+pub fn no_grad() -> NoGradGuard {
+    NoGradGuard {
+        prev: GRAD_ENABLED.with(|enabled| enabled.replace(false)),
+    }
+}
+
+/// Whether ops on the current thread record the graph needed by `backward`.
+pub fn is_grad_enabled() -> bool {
+    GRAD_ENABLED.with(|enabled| enabled.get())
+}
+
+impl Drop for NoGradGuard {
+    fn drop(&mut self) {
+        GRAD_ENABLED.with(|enabled| enabled.set(self.prev));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guards_nest_and_restore() {
+        assert!(is_grad_enabled());
+        {
+            let _outer = no_grad();
+            {
+                let _inner = no_grad();
+                assert!(!is_grad_enabled());
+            }
+            assert!(!is_grad_enabled());
+        }
+        assert!(is_grad_enabled());
+    }
+}
@@ -0,0 +1,152 @@
+use super::{lit, Float};
+
+/// The result of comparing `backward` against central finite differences,
+/// as returned by `engine::v0::gradcheck` and `engine::v1::gradcheck`.
+#[derive(Clone, Debug)]
+pub struct GradCheck<T = f64> {
+    /// One entry per input, in the order they were passed in.
+    pub inputs: Vec<InputCheck<T>>,
+    /// The first op in the graph whose backward rule disagrees with finite
+    /// differences of its own forward pass, if any.
+    pub op: Option<OpCheck<T>>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct InputCheck<T = f64> {
+    pub analytic: T,
+    pub numeric: T,
+    pub abs_error: T,
+    pub rel_error: T,
+}
+
+#[derive(Clone, Debug)]
+pub struct OpCheck<T = f64> {
+    pub op: String,
+    /// The data of the op's inputs at which the check failed.
+    pub inputs: Vec<T>,
+    pub analytic: Vec<T>,
+    pub numeric: Vec<T>,
+}
+
+impl<T: Float> GradCheck<T> {
+    /// Whether every op agrees with finite differences and every input is
+    /// within [`tolerance`] in either absolute or relative error.
+    pub fn passed(&self) -> bool {
+        self.op.is_none()
+            && self
+                .inputs
+                .iter()
+                .all(|i| i.abs_error <= tolerance() || i.rel_error <= tolerance())
+    }
+
+    pub fn max_rel_error(&self) -> T {
+        self.inputs
+            .iter()
+            .fold(T::zero(), |acc, i| acc.max(i.rel_error))
+    }
+}
+
+/// Finite difference step: the cube root of machine epsilon balances
+/// truncation and rounding error for central differences.
+pub fn step<T: Float>() -> T {
+    T::epsilon().cbrt()
+}
+
+/// Error allowed before a gradient is reported as wrong, about `1e-4` for
+/// `f64` and `2e-2` for `f32`.
+pub fn tolerance<T: Float>() -> T {
+    T::epsilon().powf(lit(0.25))
+}
+
+/// Central differences of `f` with respect to every coordinate of `x`.
+pub(crate) fn numeric<T: Float>(f: impl Fn(&[T]) -> T, x: &[T]) -> Vec<T> {
+    let h = step::<T>();
+
+    (0..x.len())
+        .map(|i| {
+            let mut plus = x.to_vec();
+            let mut minus = x.to_vec();
+            plus[i] += h;
+            minus[i] = minus[i] - h;
+
+            (f(&plus) - f(&minus)) / (h + h)
+        })
+        .collect()
+}
+
+pub(crate) fn compare<T: Float>(analytic: &[T], numeric: &[T]) -> Vec<InputCheck<T>> {
+    analytic
+        .iter()
+        .zip(numeric.iter())
+        .map(|(&analytic, &numeric)| {
+            let abs_error = (analytic - numeric).abs();
+            let scale = analytic.abs().max(numeric.abs());
+            let rel_error = if scale > T::zero() {
+                abs_error / scale
+            } else {
+                T::zero()
+            };
+
+            InputCheck {
+                analytic,
+                numeric,
+                abs_error,
+                rel_error,
+            }
+        })
+        .collect()
+}
+
+/// Checks a single op in isolation. `apply` must evaluate the op on fresh
+/// leaves holding the given data and return its output together with the
+/// gradients its backward rule assigns to those leaves.
+pub(crate) fn check_op<T: Float>(
+    op: String,
+    inputs: Vec<T>,
+    apply: impl Fn(&[T]) -> (T, Vec<T>),
+) -> Option<OpCheck<T>> {
+    let (_, analytic) = apply(&inputs);
+    let numeric = numeric(|x| apply(x).0, &inputs);
+
+    let ok = analytic
+        .iter()
+        .zip(numeric.iter())
+        .all(|(&a, &n)| (a - n).abs() <= tolerance::<T>() * T::one().max(a.abs()).max(n.abs()));
+
+    if ok {
+        None
+    } else {
+        Some(OpCheck {
+            op,
+            inputs,
+            analytic,
+            numeric,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_op_rejects_wrong_rule() {
+        let wrong = check_op("Tanh".to_string(), vec![0.5], |x: &[f64]| {
+            (x[0].tanh(), vec![x[0].powi(2)])
+        });
+        let right = check_op("Tanh".to_string(), vec![0.5], |x: &[f64]| {
+            (x[0].tanh(), vec![1.0 - x[0].tanh().powi(2)])
+        });
+
+        assert_eq!("Tanh", wrong.unwrap().op);
+        assert!(right.is_none());
+    }
+    #[test]
+    fn compare_reports_relative_error() {
+        let checks = compare(&[2.0, 0.0], &[1.0, 0.0]);
+
+        assert_eq!(1.0, checks[0].abs_error);
+        assert_eq!(0.5, checks[0].rel_error);
+        assert_eq!(0.0, checks[1].rel_error);
+    }
+}
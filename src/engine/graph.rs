@@ -0,0 +1,143 @@
+use super::program;
+use super::Float;
+use crate::error::Error;
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Identifies a node of a graph. Every copy of a node has the same id, and
+/// no two nodes share one, even after the first has been dropped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub(crate) usize);
+
+/// A fresh id for a new node, taken from a process-wide counter.
+pub(crate) fn next_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// What a node computes, with the parameters of the op.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpKind<T> {
+    Add,
+    Mul,
+    Div,
+    Powf(T),
+    Tanh,
+    Exp,
+    Ln,
+    Relu,
+    LeakyRelu(T),
+    Sigmoid,
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
+    /// A user-defined `Function`, named by its `Debug` output.
+    Custom(String),
+    Leaf,
+}
+
+/// A read-only view of one node: its op, the nodes it was computed from in
+/// operand order, its data and grad at the time the view was taken, and the
+/// label given to it with `set_label`, if any.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node<T> {
+    pub id: NodeId,
+    pub op: OpKind<T>,
+    pub parents: Vec<NodeId>,
+    pub data: T,
+    pub grad: T,
+    pub label: Option<String>,
+}
+
+/// The name given to a node with `set_label`. Clones of a node share it, so
+/// a parameter labelled after a graph was built from it shows the label in
+/// that graph too.
+#[derive(Clone, Default)]
+pub(crate) struct Label(Rc<RefCell<Option<String>>>);
+
+impl Label {
+    pub fn new() -> Label {
+        Label::default()
+    }
+
+    pub fn set(&self, label: String) {
+        *self.0.borrow_mut() = Some(label);
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.borrow().clone()
+    }
+}
+
+/// Receives every node of a graph in topological order. Implemented for
+/// closures, so `value.visit(&mut |node| ...)` works.
+pub trait Visitor<T> {
+    fn visit(&mut self, node: &Node<T>);
+}
+
+impl<T, F: FnMut(&Node<T>)> Visitor<T> for F {
+    fn visit(&mut self, node: &Node<T>) {
+        self(node)
+    }
+}
+
+impl<T> OpKind<T> {
+    /// How many parents a node with this op has. Custom ops take any
+    /// number.
+    pub fn arity(&self) -> Option<usize> {
+        match self {
+            OpKind::Add | OpKind::Mul | OpKind::Div => Some(2),
+            OpKind::Custom(_) => None,
+            OpKind::Leaf => Some(0),
+            _ => Some(1),
+        }
+    }
+}
+
+impl<T: Float> Display for OpKind<T> {
+    /// The op as graph dumps label it: `Powf(2.0)`, `Tanh`, or the name of
+    /// a custom op.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpKind::Custom(name) => f.write_str(name),
+            op => write!(f, "{:?}", op),
+        }
+    }
+}
+
+/// Checks that a node with op `op` has `parents` parents.
+pub(crate) fn check_arity<T: Float>(op: &OpKind<T>, parents: usize) -> Result<(), Error<T>> {
+    match op.arity() {
+        Some(arity) if arity != parents => Err(Error::InvalidOp(format!(
+            "{} takes {} inputs, found {}",
+            op, arity, parents
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The public kind of an op as recorded for tracing.
+pub(crate) fn kind<T: Float>(op: &program::Op<T>) -> OpKind<T> {
+    match op {
+        program::Op::Add => OpKind::Add,
+        program::Op::Mul => OpKind::Mul,
+        program::Op::Div => OpKind::Div,
+        program::Op::Powf(n) => OpKind::Powf(*n),
+        program::Op::Tanh => OpKind::Tanh,
+        program::Op::Exp => OpKind::Exp,
+        program::Op::Ln => OpKind::Ln,
+        program::Op::Relu => OpKind::Relu,
+        program::Op::LeakyRelu(alpha) => OpKind::LeakyRelu(*alpha),
+        program::Op::Sigmoid => OpKind::Sigmoid,
+        program::Op::Sqrt => OpKind::Sqrt,
+        program::Op::Abs => OpKind::Abs,
+        program::Op::Sin => OpKind::Sin,
+        program::Op::Cos => OpKind::Cos,
+        program::Op::Custom(f) => OpKind::Custom(format!("{:?}", f)),
+        program::Op::None => OpKind::Leaf,
+        program::Op::Sub | program::Op::Neg => unreachable!("only optimized programs subtract"),
+    }
+}
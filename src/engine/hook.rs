@@ -0,0 +1,28 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Hook<T> = Box<dyn FnMut(T) -> T>;
+
+/// The closures registered on a node with `register_hook`. Clones of a node
+/// share them, so a hook registered on a parameter fires wherever that
+/// parameter appears in a graph.
+#[derive(Clone)]
+pub(crate) struct Hooks<T>(Rc<RefCell<Vec<Hook<T>>>>);
+
+impl<T> Hooks<T> {
+    pub fn new() -> Hooks<T> {
+        Hooks(Rc::new(RefCell::new(vec![])))
+    }
+
+    pub fn push(&self, hook: impl FnMut(T) -> T + 'static) {
+        self.0.borrow_mut().push(Box::new(hook));
+    }
+
+    /// Passes `grad` through every hook in the order they were registered.
+    pub fn run(&self, grad: T) -> T {
+        self.0
+            .borrow_mut()
+            .iter_mut()
+            .fold(grad, |grad, hook| hook(grad))
+    }
+}
@@ -1,2 +1,160 @@
+/// Implements the operator overloads that every engine derives from its
+/// owned `Value op Value` impls and `Value::new`: the `&Value` and scalar
+/// operand combinations, negation of `&Value`, the compound assignment
+/// operators, and `Sum`/`Product` over owned and borrowed values. A scalar on
+/// the left is always an `f64`, so `2.0 * &x` infers for every `T`.
+macro_rules! impl_ops {
+    () => {
+        impl_ops!(Add, add, AddAssign, add_assign);
+        impl_ops!(Sub, sub, SubAssign, sub_assign);
+        impl_ops!(Mul, mul, MulAssign, mul_assign);
+        impl_ops!(Div, div, DivAssign, div_assign);
+
+        impl<T: $crate::engine::Float> std::ops::Neg for &Value<T> {
+            type Output = Value<T>;
+
+            fn neg(self) -> Self::Output {
+                -self.clone()
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::iter::Sum for Value<T> {
+            fn sum<I: Iterator<Item = Value<T>>>(iter: I) -> Value<T> {
+                iter.fold(Value::new(T::zero()), |a, b| a + b)
+            }
+        }
+
+        impl<'a, T: $crate::engine::Float> std::iter::Sum<&'a Value<T>> for Value<T> {
+            fn sum<I: Iterator<Item = &'a Value<T>>>(iter: I) -> Value<T> {
+                iter.fold(Value::new(T::zero()), |a, b| a + b)
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::iter::Product for Value<T> {
+            fn product<I: Iterator<Item = Value<T>>>(iter: I) -> Value<T> {
+                iter.fold(Value::new(T::one()), |a, b| a * b)
+            }
+        }
+
+        impl<'a, T: $crate::engine::Float> std::iter::Product<&'a Value<T>> for Value<T> {
+            fn product<I: Iterator<Item = &'a Value<T>>>(iter: I) -> Value<T> {
+                iter.fold(Value::new(T::one()), |a, b| a * b)
+            }
+        }
+    };
+    ($op:ident, $method:ident, $op_assign:ident, $method_assign:ident) => {
+        impl<T: $crate::engine::Float> std::ops::$op<&Value<T>> for Value<T> {
+            type Output = Value<T>;
+
+            fn $method(self, other: &Value<T>) -> Self::Output {
+                std::ops::$op::$method(self, other.clone())
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::ops::$op<Value<T>> for &Value<T> {
+            type Output = Value<T>;
+
+            fn $method(self, other: Value<T>) -> Self::Output {
+                std::ops::$op::$method(self.clone(), other)
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::ops::$op<&Value<T>> for &Value<T> {
+            type Output = Value<T>;
+
+            fn $method(self, other: &Value<T>) -> Self::Output {
+                std::ops::$op::$method(self.clone(), other.clone())
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::ops::$op<T> for Value<T> {
+            type Output = Value<T>;
+
+            fn $method(self, other: T) -> Self::Output {
+                std::ops::$op::$method(self, Value::new(other))
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::ops::$op<T> for &Value<T> {
+            type Output = Value<T>;
+
+            fn $method(self, other: T) -> Self::Output {
+                std::ops::$op::$method(self.clone(), Value::new(other))
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::ops::$op<Value<T>> for f64 {
+            type Output = Value<T>;
+
+            fn $method(self, other: Value<T>) -> Self::Output {
+                std::ops::$op::$method(Value::new($crate::engine::lit(self)), other)
+            }
+        }
+
+        impl<T: $crate::engine::Float> std::ops::$op<&Value<T>> for f64 {
+            type Output = Value<T>;
+
+            fn $method(self, other: &Value<T>) -> Self::Output {
+                std::ops::$op::$method(Value::new($crate::engine::lit(self)), other.clone())
+            }
+        }
+
+        impl<T: $crate::engine::Float, R> std::ops::$op_assign<R> for Value<T>
+        where
+            Value<T>: std::ops::$op<R, Output = Value<T>>,
+        {
+            fn $method_assign(&mut self, other: R) {
+                let lhs = std::mem::replace(self, Value::new(T::zero()));
+                *self = std::ops::$op::$method(lhs, other);
+            }
+        }
+    };
+}
+
+pub mod anomaly;
+pub mod backend;
+mod codegen;
+mod dot;
+pub mod dual;
+pub mod formula;
+pub mod function;
+pub mod grad_mode;
+pub mod gradcheck;
+pub mod graph;
+mod hook;
+pub mod program;
+pub mod sync;
+pub mod tensor;
 pub mod v0;
 pub mod v1;
+pub mod v2;
+
+pub use anomaly::{detect_anomaly, is_anomaly_enabled, Anomaly};
+pub use backend::Backend;
+pub use function::Function;
+pub use grad_mode::{is_grad_enabled, no_grad, NoGradGuard};
+
+use std::fmt::Debug;
+use std::ops::AddAssign;
+
+/// The scalar type a `Value` is generic over. Implemented for `f32` and
+/// `f64`; every engine defaults to `f64`.
+pub trait Float: num_traits::Float + AddAssign + Debug + 'static {}
+
+impl<T: num_traits::Float + AddAssign + Debug + 'static> Float for T {}
+
+/// Converts an `f64` constant into `T`.
+pub(crate) fn lit<T: Float>(x: f64) -> T {
+    T::from(x).unwrap()
+}
+
+/// Derivative of `abs`, taking the subgradient at zero to be zero.
+fn sign<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        T::one()
+    } else if x < T::zero() {
+        -T::one()
+    } else {
+        T::zero()
+    }
+}
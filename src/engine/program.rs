@@ -0,0 +1,534 @@
+use super::{lit, sign, Float, Function};
+use crate::error::{check_len, Error};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// A graph flattened into a list of instructions over a fixed set of slots,
+/// one per node in topological order. Running it again on new input data
+/// only rewrites the slots, so a fixed computation such as an `MLP` loss can
+/// be evaluated and differentiated any number of times without building a
+/// single `Value`. Created by `engine::v0::trace` and `engine::v1::trace`.
+#[derive(Clone)]
+pub struct Program<T = f64> {
+    pub(crate) code: Vec<Instr<T>>,
+    pub(crate) data: Vec<T>,
+    grad: Vec<T>,
+    pub(crate) inputs: Vec<usize>,
+    pub(crate) params: Vec<usize>,
+    pub(crate) outputs: Vec<usize>,
+    scratch: Vec<T>,
+}
+
+/// Computes slot `i` of a program, where `i` is the instruction's position,
+/// from the slots in `args`.
+#[derive(Clone, Debug)]
+pub(crate) struct Instr<T> {
+    pub op: Op<T>,
+    pub args: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Op<T> {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    Powf(T),
+    Tanh,
+    Exp,
+    Ln,
+    Relu,
+    LeakyRelu(T),
+    Sigmoid,
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
+    Custom(Rc<dyn Function<T>>),
+    None,
+}
+
+impl<T: Float> Instr<T> {
+    /// Output of a non-leaf instruction given the data of every slot.
+    fn eval(&self, data: &[T], scratch: &mut Vec<T>) -> T {
+        match &self.op {
+            Op::Custom(f) => {
+                scratch.clear();
+                scratch.extend(self.args.iter().map(|&a| data[a]));
+                f.forward(scratch)
+            }
+            op => {
+                let x = data[self.args[0]];
+                let y = self.args.get(1).map_or(T::zero(), |&b| data[b]);
+                op.eval(x, y)
+            }
+        }
+    }
+}
+
+impl<T: Float> Op<T> {
+    /// Output of a built-in op on `x` and, for binary ops, `y`.
+    pub(crate) fn eval(&self, x: T, y: T) -> T {
+        match *self {
+            Op::Add => x + y,
+            Op::Sub => x - y,
+            Op::Mul => x * y,
+            Op::Div => x / y,
+            Op::Neg => -x,
+            Op::Powf(n) => x.powf(n),
+            Op::Tanh => x.tanh(),
+            Op::Exp => x.exp(),
+            Op::Ln => x.ln(),
+            Op::Relu => x.max(T::zero()),
+            Op::LeakyRelu(alpha) => {
+                if x > T::zero() {
+                    x
+                } else {
+                    alpha * x
+                }
+            }
+            Op::Sigmoid => T::one() / (T::one() + (-x).exp()),
+            Op::Sqrt => x.sqrt(),
+            Op::Abs => x.abs(),
+            Op::Sin => x.sin(),
+            Op::Cos => x.cos(),
+            Op::Custom(_) | Op::None => unreachable!("not a built-in op"),
+        }
+    }
+
+    /// Partial derivatives of a built-in op with respect to `x` and `y`,
+    /// given its output `out`.
+    pub(crate) fn partials(&self, x: T, y: T, out: T) -> (T, T) {
+        match *self {
+            Op::Add => (T::one(), T::one()),
+            Op::Sub => (T::one(), -T::one()),
+            Op::Mul => (y, x),
+            Op::Div => (T::one() / y, -x / y.powi(2)),
+            Op::Neg => (-T::one(), T::zero()),
+            Op::Powf(n) => (n * x.powf(n - T::one()), T::zero()),
+            Op::Tanh => (T::one() - out.powi(2), T::zero()),
+            Op::Exp => (out, T::zero()),
+            Op::Ln => (T::one() / x, T::zero()),
+            Op::Relu => (if x > T::zero() { T::one() } else { T::zero() }, T::zero()),
+            Op::LeakyRelu(alpha) => (if x > T::zero() { T::one() } else { alpha }, T::zero()),
+            Op::Sigmoid => (out * (T::one() - out), T::zero()),
+            Op::Sqrt => (lit::<T>(0.5) / out, T::zero()),
+            Op::Abs => (sign(x), T::zero()),
+            Op::Sin => (x.cos(), T::zero()),
+            Op::Cos => (-x.sin(), T::zero()),
+            Op::Custom(_) | Op::None => unreachable!("not a built-in op"),
+        }
+    }
+}
+
+impl<T: Float> Program<T> {
+    /// Writes `inputs` into the input slots, in the order they were traced,
+    /// and recomputes every other slot. Panics unless there is exactly one
+    /// input per input slot.
+    pub fn forward(&mut self, inputs: &[T]) {
+        self.try_forward(inputs)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `forward`, failing unless there is exactly one input per input
+    /// slot.
+    pub fn try_forward(&mut self, inputs: &[T]) -> Result<(), Error<T>> {
+        check_len(self.inputs.len(), inputs.len())?;
+
+        for (&slot, &x) in self.inputs.iter().zip(inputs.iter()) {
+            self.data[slot] = x;
+        }
+
+        for i in 0..self.code.len() {
+            if let Op::None = self.code[i].op {
+                continue;
+            }
+            self.data[i] = self.code[i].eval(&self.data, &mut self.scratch);
+        }
+
+        Ok(())
+    }
+
+    /// Differentiates the sum of the outputs with respect to every slot,
+    /// using the data of the last `forward`.
+    pub fn backward(&mut self) {
+        self.grad.iter_mut().for_each(|g| *g = T::zero());
+        for &slot in self.outputs.iter() {
+            self.grad[slot] += T::one();
+        }
+
+        for i in (0..self.code.len()).rev() {
+            let instr = &self.code[i];
+            let grad = self.grad[i];
+
+            match &instr.op {
+                Op::None => {}
+                Op::Custom(f) => {
+                    self.scratch.clear();
+                    self.scratch
+                        .extend(instr.args.iter().map(|&a| self.data[a]));
+                    let grads = f.backward(&self.scratch, self.data[i], grad);
+
+                    for (&a, g) in instr.args.iter().zip(grads) {
+                        self.grad[a] += g;
+                    }
+                }
+                op => {
+                    let x = self.data[instr.args[0]];
+                    let y = instr.args.get(1).map_or(T::zero(), |&b| self.data[b]);
+                    let (dx, dy) = op.partials(x, y, self.data[i]);
+
+                    self.grad[instr.args[0]] += dx * grad;
+                    if let Some(&b) = instr.args.get(1) {
+                        self.grad[b] += dy * grad;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rewrites the program into an equivalent and usually smaller one: ops
+    /// on constants are folded, identical nodes merged, `x * -1 + y` turned
+    /// into `y - x`, and nodes that no output depends on dropped. Inputs and
+    /// params keep their order, so callers see no difference.
+    pub fn optimize(&mut self) -> OptimizeReport {
+        let mut report = OptimizeReport {
+            before: self.len(),
+            ..Default::default()
+        };
+        let variables: HashSet<usize> = self
+            .inputs
+            .iter()
+            .chain(self.params.iter())
+            .copied()
+            .collect();
+
+        let mut code: Vec<Instr<T>> = vec![];
+        let mut data: Vec<T> = vec![];
+        let mut constant: Vec<bool> = vec![];
+        let mut slots: Vec<usize> = vec![];
+        let mut seen: HashMap<Key, usize> = HashMap::new();
+
+        for (i, instr) in self.code.iter().enumerate() {
+            let mut op = instr.op.clone();
+            let mut args: Vec<usize> = instr.args.iter().map(|&a| slots[a]).collect();
+            let mut value = self.data[i];
+
+            if variables.contains(&i) {
+                slots.push(code.len());
+                code.push(Instr { op, args });
+                data.push(value);
+                constant.push(false);
+                continue;
+            }
+
+            if !args.is_empty() && args.iter().all(|&a| constant[a]) {
+                value = Instr { op, args }.eval(&data, &mut self.scratch);
+                op = Op::None;
+                args = vec![];
+                report.folded += 1;
+            }
+
+            let is_minus_one = |a: usize| constant[a] && data[a] == -T::one();
+            let is_neg = |a: usize| matches!(code[a].op, Op::Neg);
+            match op {
+                Op::Mul if args.iter().any(|&a| is_minus_one(a)) => {
+                    let x = if is_minus_one(args[1]) {
+                        args[0]
+                    } else {
+                        args[1]
+                    };
+                    op = Op::Neg;
+                    args = vec![x];
+                    report.simplified += 1;
+                }
+                Op::Add if args.iter().any(|&a| is_neg(a)) => {
+                    let (y, neg) = if is_neg(args[1]) {
+                        (args[0], args[1])
+                    } else {
+                        (args[1], args[0])
+                    };
+                    op = Op::Sub;
+                    args = vec![y, code[neg].args[0]];
+                    report.simplified += 1;
+                }
+                _ => {}
+            }
+
+            let key = Key::new(&op, &args, value);
+            if let Some(&slot) = seen.get(&key) {
+                slots.push(slot);
+                report.merged += 1;
+                continue;
+            }
+
+            seen.insert(key, code.len());
+            slots.push(code.len());
+            constant.push(matches!(op, Op::None));
+            code.push(Instr { op, args });
+            data.push(value);
+        }
+
+        let remap =
+            |ids: &[usize], slots: &[usize]| ids.iter().map(|&i| slots[i]).collect::<Vec<usize>>();
+        let (inputs, params, outputs) = (
+            remap(&self.inputs, &slots),
+            remap(&self.params, &slots),
+            remap(&self.outputs, &slots),
+        );
+
+        let mut live = vec![false; code.len()];
+        for &slot in inputs.iter().chain(params.iter()).chain(outputs.iter()) {
+            live[slot] = true;
+        }
+        for i in (0..code.len()).rev() {
+            if live[i] {
+                for &a in code[i].args.iter() {
+                    live[a] = true;
+                }
+            }
+        }
+
+        let mut slots: Vec<usize> = vec![0; code.len()];
+        self.code = vec![];
+        self.data = vec![];
+        for (i, (mut instr, value)) in code.into_iter().zip(data).enumerate() {
+            if live[i] {
+                slots[i] = self.code.len();
+                instr.args.iter_mut().for_each(|a| *a = slots[*a]);
+                self.code.push(instr);
+                self.data.push(value);
+            }
+        }
+        self.inputs = remap(&inputs, &slots);
+        self.params = remap(&params, &slots);
+        self.outputs = remap(&outputs, &slots);
+        self.grad = vec![T::zero(); self.code.len()];
+
+        report.after = self.len();
+        report
+    }
+
+    pub fn outputs(&self) -> Vec<T> {
+        self.outputs.iter().map(|&slot| self.data[slot]).collect()
+    }
+
+    /// Data of the parameters, in the order they were traced. Parameters
+    /// that are the same node, such as v0 clones, appear once.
+    pub fn params(&self) -> Vec<T> {
+        self.params.iter().map(|&slot| self.data[slot]).collect()
+    }
+
+    /// Overwrites the data of the parameters, e.g. after a gradient step.
+    /// Takes effect on the next `forward`. Panics unless there is exactly
+    /// one value per parameter.
+    pub fn set_params(&mut self, params: &[T]) {
+        self.try_set_params(params)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `set_params`, failing unless there is exactly one value per
+    /// parameter.
+    pub fn try_set_params(&mut self, params: &[T]) -> Result<(), Error<T>> {
+        check_len(self.params.len(), params.len())?;
+
+        for (&slot, &p) in self.params.iter().zip(params.iter()) {
+            self.data[slot] = p;
+        }
+
+        Ok(())
+    }
+
+    /// Gradients of the parameters from the last `backward`.
+    pub fn param_grads(&self) -> Vec<T> {
+        self.params.iter().map(|&slot| self.grad[slot]).collect()
+    }
+
+    /// Gradients of the inputs from the last `backward`.
+    pub fn input_grads(&self) -> Vec<T> {
+        self.inputs.iter().map(|&slot| self.grad[slot]).collect()
+    }
+
+    /// Number of instructions, leaves included.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+}
+
+/// Node counts of a `Program` before and after `Program::optimize`, and
+/// how often each rewrite fired.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OptimizeReport {
+    pub before: usize,
+    pub after: usize,
+    /// Ops whose inputs were all constants, replaced by their result.
+    pub folded: usize,
+    /// Nodes identical to an earlier one, replaced by it.
+    pub merged: usize,
+    /// Multiplications by `-1` turned into negations and additions of a
+    /// negation turned into subtractions.
+    pub simplified: usize,
+}
+
+/// What makes two nodes interchangeable: constants by value, ops by kind,
+/// parameters and operands. Operands of commutative ops are sorted.
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Constant(String),
+    Node(String, Vec<usize>),
+}
+
+impl Key {
+    fn new<T: Float>(op: &Op<T>, args: &[usize], data: T) -> Key {
+        let mut args = args.to_vec();
+
+        match op {
+            Op::None => return Key::Constant(format!("{:?}", data)),
+            Op::Add | Op::Mul => args.sort_unstable(),
+            _ => {}
+        }
+        let op = match op {
+            Op::Custom(f) => format!("Custom({:p})", Rc::as_ptr(f)),
+            op => format!("{:?}", op),
+        };
+
+        Key::Node(op, args)
+    }
+}
+
+/// Collects the nodes of an engine graph into a `Program`. Engines push
+/// every node once, after its children, keyed by their own node ids.
+pub(crate) struct Tracer<T> {
+    slots: HashMap<usize, usize>,
+    code: Vec<Instr<T>>,
+    data: Vec<T>,
+}
+
+impl<T: Float> Tracer<T> {
+    pub fn new() -> Tracer<T> {
+        Tracer {
+            slots: HashMap::new(),
+            code: vec![],
+            data: vec![],
+        }
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    pub fn push(&mut self, id: usize, op: Op<T>, args: &[usize], data: T) {
+        let args = args.iter().map(|a| self.slots[a]).collect();
+
+        self.slots.insert(id, self.code.len());
+        self.code.push(Instr { op, args });
+        self.data.push(data);
+    }
+
+    pub fn finish(self, outputs: &[usize], inputs: &[usize], params: &[usize]) -> Program<T> {
+        let slots = |ids: &[usize]| ids.iter().map(|id| self.slots[id]).collect::<Vec<usize>>();
+        let mut params = slots(params);
+        let mut seen = HashSet::new();
+        params.retain(|&slot| seen.insert(slot));
+
+        Program {
+            inputs: slots(inputs),
+            params,
+            outputs: slots(outputs),
+            grad: vec![T::zero(); self.data.len()],
+            code: self.code,
+            data: self.data,
+            scratch: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::v1::{trace, Value};
+    use crate::error::Error;
+
+    #[test]
+    fn replays_graph_on_new_inputs() {
+        let f =
+            |a: &Value, b: &Value, w: &Value| ((a * w).tanh() + b / w).powf(2.0) + a.clone().exp();
+        let (a, b, w) = (Value::new(0.5), Value::new(-1.5), Value::new(0.3));
+        let mut program = trace(&[f(&a, &b, &w)], &[a, b], &[w]);
+
+        program.forward(&[1.2, 0.4]);
+        program.backward();
+
+        let (a, b, w) = (Value::new(1.2), Value::new(0.4), Value::new(0.3));
+        let out = f(&a, &b, &w);
+        out.backward();
+
+        assert_eq!(vec![*out.data.borrow()], program.outputs());
+        assert_eq!(
+            vec![*a.grad.borrow(), *b.grad.borrow()],
+            program.input_grads()
+        );
+        assert_eq!(vec![*w.grad.borrow()], program.param_grads());
+    }
+    #[test]
+    fn unused_inputs_and_shared_nodes_get_one_slot() {
+        let (a, b) = (Value::new(2.0), Value::new(3.0));
+        let c = &a * &a;
+        let mut program = trace(&[&c + &c], &[a, b], &[]);
+
+        program.forward(&[3.0, 1.0]);
+        program.backward();
+
+        assert_eq!(4, program.len());
+        assert_eq!(vec![18.0], program.outputs());
+        assert_eq!(vec![12.0, 0.0], program.input_grads());
+    }
+    #[test]
+    fn optimize_folds_merges_and_simplifies() {
+        let a = Value::new(0.5);
+        let c = (Value::new(2.0) * Value::new(3.0)).exp();
+        let y = a.clone().tanh() * c + a.clone().tanh() - &a;
+        let mut program = trace(&[y], std::slice::from_ref(&a), &[]);
+        let mut optimized = program.clone();
+
+        let report = optimized.optimize();
+        program.forward(&[-1.5]);
+        program.backward();
+        optimized.forward(&[-1.5]);
+        optimized.backward();
+
+        assert_eq!(program.outputs(), optimized.outputs());
+        assert_eq!(program.input_grads(), optimized.input_grads());
+        assert_eq!((2, 2), (report.folded, report.simplified));
+        assert_eq!(report.before, program.len());
+        assert_eq!((1, 6), (report.merged, report.after));
+    }
+    #[test]
+    fn try_forward_rejects_the_wrong_number_of_inputs() {
+        let (a, w) = (Value::new(0.5), Value::new(0.3));
+        let mut program = trace(&[&a * &w], std::slice::from_ref(&a), &[w]);
+
+        assert_eq!(
+            Err(Error::LengthMismatch {
+                expected: 1,
+                found: 2
+            }),
+            program.try_forward(&[1.0, 2.0])
+        );
+        assert!(program.try_set_params(&[]).is_err());
+        assert_eq!(Ok(()), program.try_forward(&[2.0]));
+        assert_eq!(vec![0.6], program.outputs());
+    }
+    #[test]
+    #[should_panic(expected = "expected 1 values, found 0")]
+    fn forward_panics_on_the_wrong_number_of_inputs() {
+        let (a, w) = (Value::new(0.5), Value::new(0.3));
+        let mut program = trace(&[&a * &w], std::slice::from_ref(&a), &[w]);
+
+        program.forward(&[]);
+    }
+}
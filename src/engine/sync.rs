@@ -0,0 +1,462 @@
+use super::grad_mode::is_grad_enabled;
+use super::{lit, sign, Backend, Float};
+use crate::error::Error;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::mem;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// The `engine::v1` design on `Arc` and locks instead of `Rc<RefCell<_>>`,
+/// so values can be shared between threads. Each thread builds its own
+/// graph on top of shared leaves; since every gradient cell is a mutex and
+/// `backward` only ever adds to it, concurrent `backward` calls reduce into
+/// the shared leaves' grads.
+#[derive(Clone)]
+pub struct Value<T = f64> {
+    pub data: Arc<RwLock<T>>,
+    pub grad: Arc<Mutex<T>>,
+    pub _prev: Vec<Arc<Value<T>>>,
+    _op: Op<T>,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Op<T> {
+    Add,
+    Mul,
+    Div,
+    Powf(T),
+    Tanh,
+    Exp,
+    Ln,
+    Relu,
+    LeakyRelu(T),
+    Sigmoid,
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
+    None,
+}
+
+impl<T: Float> Value<T> {
+    pub fn new(data: T) -> Value<T> {
+        Value {
+            data: Arc::new(RwLock::new(data)),
+            grad: Arc::new(Mutex::new(T::zero())),
+            _prev: vec![],
+            _op: Op::None,
+        }
+    }
+
+    pub fn data(&self) -> T {
+        *self.data.read().unwrap()
+    }
+
+    pub fn grad(&self) -> T {
+        *self.grad.lock().unwrap()
+    }
+
+    pub fn tanh(self) -> Value<T> {
+        let data: T = self.data().tanh();
+        self._unary(data, Op::Tanh)
+    }
+
+    pub fn powf(self, n: T) -> Value<T> {
+        let data: T = self.data().powf(n);
+        self._unary(data, Op::Powf(n))
+    }
+
+    pub fn exp(self) -> Value<T> {
+        let data: T = self.data().exp();
+        self._unary(data, Op::Exp)
+    }
+
+    pub fn ln(self) -> Value<T> {
+        let data: T = self.data().ln();
+        self._unary(data, Op::Ln)
+    }
+
+    pub fn relu(self) -> Value<T> {
+        let data: T = self.data().max(T::zero());
+        self._unary(data, Op::Relu)
+    }
+
+    pub fn leaky_relu(self, alpha: T) -> Value<T> {
+        let x: T = self.data();
+        let data: T = if x > T::zero() { x } else { alpha * x };
+        self._unary(data, Op::LeakyRelu(alpha))
+    }
+
+    pub fn sigmoid(self) -> Value<T> {
+        let data: T = T::one() / (T::one() + (-self.data()).exp());
+        self._unary(data, Op::Sigmoid)
+    }
+
+    pub fn sqrt(self) -> Value<T> {
+        let data: T = self.data().sqrt();
+        self._unary(data, Op::Sqrt)
+    }
+
+    pub fn abs(self) -> Value<T> {
+        let data: T = self.data().abs();
+        self._unary(data, Op::Abs)
+    }
+
+    pub fn sin(self) -> Value<T> {
+        let data: T = self.data().sin();
+        self._unary(data, Op::Sin)
+    }
+
+    pub fn cos(self) -> Value<T> {
+        let data: T = self.data().cos();
+        self._unary(data, Op::Cos)
+    }
+
+    fn _unary(self, data: T, _op: Op<T>) -> Value<T> {
+        Value::_node(data, vec![self], _op)
+    }
+
+    fn _binary(self, other: Value<T>, data: T, _op: Op<T>) -> Value<T> {
+        Value::_node(data, vec![self, other], _op)
+    }
+
+    fn _node(data: T, prev: Vec<Value<T>>, _op: Op<T>) -> Value<T> {
+        if !is_grad_enabled() {
+            return Value::new(data);
+        }
+
+        Value {
+            data: Arc::new(RwLock::new(data)),
+            grad: Arc::new(Mutex::new(T::zero())),
+            _prev: prev.into_iter().map(Arc::new).collect(),
+            _op,
+        }
+    }
+
+    /// Backpropagates from `self` into a scratch buffer private to this
+    /// call and then adds the result to every node's `grad`, so grads left
+    /// by earlier calls, or being added by other threads through a shared
+    /// subexpression, are accumulated rather than propagated again.
+    pub fn backward(&self) {
+        let topo = self.topo();
+        let key = |v: &Value<T>| Arc::as_ptr(&v.grad);
+
+        let mut scratch: HashMap<*const Mutex<T>, T> = HashMap::new();
+        scratch.insert(key(self), T::one());
+
+        for v in topo.iter().rev() {
+            let grad = scratch.get(&key(v)).copied().unwrap_or_else(T::zero);
+            for (child, g) in v._prev.iter().zip(v._grads(grad)) {
+                *scratch.entry(key(child)).or_insert_with(T::zero) += g;
+            }
+        }
+
+        for v in topo.iter() {
+            if let Some(g) = scratch.get(&key(v)) {
+                *v.grad.lock().unwrap() += *g;
+            }
+        }
+    }
+
+    pub fn zero_grad(&self) {
+        for v in self.topo().iter() {
+            *v.grad.lock().unwrap() = T::zero();
+        }
+    }
+
+    /// Every node reachable from `self`, children before parents, found
+    /// with an explicit stack so that deep graphs cannot overflow the call
+    /// stack.
+    fn topo(&self) -> Vec<Value<T>> {
+        let mut result: Vec<Value<T>> = vec![];
+        let mut visited: HashSet<*const Mutex<T>> = HashSet::new();
+        let mut stack: Vec<(Value<T>, bool)> = vec![(self.clone(), false)];
+
+        while let Some((v, expanded)) = stack.pop() {
+            if expanded {
+                result.push(v);
+            } else if visited.insert(Arc::as_ptr(&v.grad)) {
+                let children: Vec<(Value<T>, bool)> = v
+                    ._prev
+                    .iter()
+                    .rev()
+                    .map(|child| (Value::clone(child), false))
+                    .collect();
+
+                stack.push((v, true));
+                stack.extend(children);
+            }
+        }
+
+        result
+    }
+
+    /// The gradients this node's backward rule passes to each of `_prev`
+    /// when its own gradient is `grad`.
+    fn _grads(&self, grad: T) -> Vec<T> {
+        let data = self.data();
+
+        match self._op {
+            Op::Add => {
+                vec![grad, grad]
+            }
+            Op::Mul => {
+                let left = self._prev[0].data();
+                let right = self._prev[1].data();
+
+                vec![right * grad, left * grad]
+            }
+            Op::Div => {
+                let left = self._prev[0].data();
+                let right = self._prev[1].data();
+
+                vec![(T::one() / right) * grad, (-left / right.powi(2)) * grad]
+            }
+            Op::Powf(n) => {
+                let left = self._prev[0].data();
+
+                vec![(n * left.powf(n - T::one())) * grad]
+            }
+            Op::Tanh => {
+                vec![(T::one() - data.powi(2)) * grad]
+            }
+            Op::Exp => {
+                vec![data * grad]
+            }
+            Op::Ln => {
+                let left = self._prev[0].data();
+
+                vec![(T::one() / left) * grad]
+            }
+            Op::Relu => {
+                let left = self._prev[0].data();
+
+                vec![if left > T::zero() { grad } else { T::zero() }]
+            }
+            Op::LeakyRelu(alpha) => {
+                let left = self._prev[0].data();
+
+                vec![if left > T::zero() { grad } else { alpha * grad }]
+            }
+            Op::Sigmoid => {
+                vec![(data * (T::one() - data)) * grad]
+            }
+            Op::Sqrt => {
+                vec![(lit::<T>(0.5) / data) * grad]
+            }
+            Op::Abs => {
+                let left = self._prev[0].data();
+
+                vec![sign(left) * grad]
+            }
+            Op::Sin => {
+                let left = self._prev[0].data();
+
+                vec![left.cos() * grad]
+            }
+            Op::Cos => {
+                let left = self._prev[0].data();
+
+                vec![-left.sin() * grad]
+            }
+            Op::None => {
+                vec![]
+            }
+        }
+    }
+}
+
+impl<T: Float> Add for Value<T> {
+    type Output = Value<T>;
+
+    fn add(self, other: Self) -> Self::Output {
+        let data: T = self.data() + other.data();
+        self._binary(other, data, Op::Add)
+    }
+}
+
+impl<T: Float> Sub for Value<T> {
+    type Output = Value<T>;
+
+    fn sub(self, other: Self) -> Self::Output {
+        self + -other
+    }
+}
+
+impl<T: Float> Mul for Value<T> {
+    type Output = Value<T>;
+
+    fn mul(self, other: Self) -> Self::Output {
+        let data: T = self.data() * other.data();
+        self._binary(other, data, Op::Mul)
+    }
+}
+
+impl<T: Float> Div for Value<T> {
+    type Output = Value<T>;
+
+    fn div(self, other: Self) -> Self::Output {
+        let data: T = self.data() / other.data();
+        self._binary(other, data, Op::Div)
+    }
+}
+
+impl<T: Float> Neg for Value<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        self * Value::new(-T::one())
+    }
+}
+
+impl_ops!();
+
+impl<T: Float> Backend<T> for Value<T> {
+    fn leaf(data: T) -> Value<T> {
+        Value::new(data)
+    }
+
+    fn add(self, other: Self) -> Value<T> {
+        self + other
+    }
+
+    fn mul(self, other: Self) -> Value<T> {
+        self * other
+    }
+
+    fn powf(self, n: T) -> Value<T> {
+        Value::powf(self, n)
+    }
+
+    fn tanh(self) -> Value<T> {
+        Value::tanh(self)
+    }
+
+    fn try_backward(&self) -> Result<Value<T>, Error<T>> {
+        Value::backward(self);
+        Ok(self.clone())
+    }
+
+    fn data(&self) -> T {
+        Value::data(self)
+    }
+
+    fn grad(&self) -> T {
+        Value::grad(self)
+    }
+
+    fn set_data(&mut self, data: T) {
+        *self.data.write().unwrap() = data;
+    }
+
+    fn zero_grad(&self) {
+        Value::zero_grad(self)
+    }
+}
+
+impl<T: Float> Debug for Value<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Value")
+            .field("data", &self.data())
+            .field("grad", &self.grad())
+            .finish()
+    }
+}
+
+/// Drops the nodes behind `_prev` one at a time instead of recursively, so
+/// that dropping a deep graph cannot overflow the stack.
+impl<T> Drop for Value<T> {
+    fn drop(&mut self) {
+        let mut stack: Vec<Arc<Value<T>>> = mem::take(&mut self._prev);
+
+        while let Some(node) = stack.pop() {
+            if let Some(mut node) = Arc::into_inner(node) {
+                stack.append(&mut node._prev);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn value_is_send_and_sync() {
+        fn assert_send_sync<V: Send + Sync>() {}
+
+        assert_send_sync::<Value>();
+        assert_send_sync::<Value<f32>>();
+    }
+    #[test]
+    fn feed_forward() {
+        let a: Value = Value::new(2.0);
+        let b = Value::new(-3.0);
+        let c = Value::new(10.0);
+        let d = &a * &b;
+        let e = d + &c;
+        let f = e.tanh();
+
+        f.backward();
+
+        let dtanh = 1.0 - f.data().powi(2);
+        assert_eq!(dtanh * -3.0, a.grad());
+        assert_eq!(dtanh * 2.0, b.grad());
+        assert_eq!(dtanh, c.grad());
+    }
+    #[test]
+    fn concurrent_backward_reduces_into_shared_leaf() {
+        let w = Value::new(3.0);
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let w = &w;
+                s.spawn(move || {
+                    let y = w * w * (i as f64);
+                    y.backward();
+                });
+            }
+        });
+
+        assert_eq!(6.0 * (0.0 + 1.0 + 2.0 + 3.0), w.grad());
+    }
+    #[test]
+    fn threads_sharing_a_subexpression_do_not_repropagate_it() {
+        let a = Value::new(2.0);
+        let b = Value::new(3.0);
+        let y = &a * &b;
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let y = &y;
+                s.spawn(move || (y * (i as f64)).backward());
+            }
+        });
+
+        assert_eq!(6.0, y.grad());
+        assert_eq!(3.0 * 6.0, a.grad());
+        assert_eq!(2.0 * 6.0, b.grad());
+
+        let c = (&a * &b) * 2.0;
+        a.zero_grad();
+        c.backward();
+        c.backward();
+
+        assert_eq!(12.0, a.grad());
+    }
+    #[test]
+    fn deep_graphs_do_not_overflow_the_stack() {
+        let x = Value::new(1.0);
+        let mut y = Value::new(0.0);
+        for _ in 0..100_000 {
+            y += &x;
+        }
+
+        y.backward();
+
+        assert_eq!(100_000.0, x.grad());
+        drop(y);
+    }
+}
@@ -0,0 +1,825 @@
+use super::grad_mode::is_grad_enabled;
+use super::program;
+use super::{lit, Float};
+use crate::error::{check_len, Error, Result};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::mem;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::rc::Rc;
+
+/// An n-dimensional array of `T` in one contiguous row-major `Vec`, with
+/// reverse-mode autograd over whole arrays. Every op produces a new
+/// contiguous tensor, so the strides are always those of its shape. Clones
+/// are cheap handles to the same node.
+#[derive(Clone)]
+pub struct Tensor<T = f64>(Rc<Node<T>>);
+
+struct Node<T> {
+    data: RefCell<Vec<T>>,
+    grad: RefCell<Vec<T>>,
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+    _prev: Vec<Tensor<T>>,
+    _op: Op<T>,
+}
+
+#[derive(Clone, Debug)]
+enum Op<T> {
+    /// A scalar op applied to every element, of `_prev[0]` alone or of
+    /// `_prev[0]` and `_prev[1]` broadcast against each other.
+    Map(program::Op<T>),
+    Sum(usize),
+    Max(usize),
+    Reshape,
+    Transpose(usize, usize),
+    Matmul,
+    None,
+}
+
+impl<T: Float> Tensor<T> {
+    pub fn new(data: Vec<T>, shape: &[usize]) -> Tensor<T> {
+        Tensor::try_new(data, shape).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `new`, failing if `data` does not have one element per index
+    /// into `shape`.
+    pub fn try_new(data: Vec<T>, shape: &[usize]) -> Result<Tensor<T>, T> {
+        check_len(shape.iter().product(), data.len())?;
+        Ok(Tensor::_node(data, shape.to_vec(), vec![], Op::None))
+    }
+
+    /// A tensor of shape `[]` holding the single element `x`.
+    pub fn scalar(x: T) -> Tensor<T> {
+        Tensor::new(vec![x], &[])
+    }
+
+    pub fn zeros(shape: &[usize]) -> Tensor<T> {
+        Tensor::full(shape, T::zero())
+    }
+
+    pub fn ones(shape: &[usize]) -> Tensor<T> {
+        Tensor::full(shape, T::one())
+    }
+
+    pub fn full(shape: &[usize], x: T) -> Tensor<T> {
+        Tensor::new(vec![x; shape.iter().product()], shape)
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.0.shape
+    }
+
+    pub fn strides(&self) -> &[usize] {
+        &self.0.strides
+    }
+
+    pub fn numel(&self) -> usize {
+        self.0.data.borrow().len()
+    }
+
+    pub fn data(&self) -> Vec<T> {
+        self.0.data.borrow().clone()
+    }
+
+    pub fn grad(&self) -> Vec<T> {
+        self.0.grad.borrow().clone()
+    }
+
+    /// Overwrites the data in place, e.g. for a parameter update. Nodes
+    /// already computed from it keep their old data.
+    pub fn set_data(&self, data: Vec<T>) {
+        assert_eq!(data.len(), self.numel(), "data does not fit shape");
+        *self.0.data.borrow_mut() = data;
+    }
+
+    /// The element at `index`, one coordinate per dimension.
+    pub fn at(&self, index: &[usize]) -> T {
+        assert_eq!(index.len(), self.0.shape.len(), "wrong number of indices");
+        let offset: usize = index
+            .iter()
+            .zip(self.0.shape.iter().zip(&self.0.strides))
+            .map(|(&i, (&n, &stride))| {
+                assert!(i < n, "index {} out of bounds for dimension of {}", i, n);
+                i * stride
+            })
+            .sum();
+
+        self.0.data.borrow()[offset]
+    }
+
+    /// The only element of a tensor with one element.
+    pub fn item(&self) -> T {
+        assert_eq!(
+            self.numel(),
+            1,
+            "item of a tensor with {} elements",
+            self.numel()
+        );
+        self.0.data.borrow()[0]
+    }
+
+    pub fn powf(&self, n: T) -> Tensor<T> {
+        self._map(program::Op::Powf(n))
+    }
+
+    pub fn tanh(&self) -> Tensor<T> {
+        self._map(program::Op::Tanh)
+    }
+
+    pub fn exp(&self) -> Tensor<T> {
+        self._map(program::Op::Exp)
+    }
+
+    pub fn ln(&self) -> Tensor<T> {
+        self._map(program::Op::Ln)
+    }
+
+    pub fn relu(&self) -> Tensor<T> {
+        self._map(program::Op::Relu)
+    }
+
+    pub fn leaky_relu(&self, alpha: T) -> Tensor<T> {
+        self._map(program::Op::LeakyRelu(alpha))
+    }
+
+    pub fn sigmoid(&self) -> Tensor<T> {
+        self._map(program::Op::Sigmoid)
+    }
+
+    pub fn sqrt(&self) -> Tensor<T> {
+        self._map(program::Op::Sqrt)
+    }
+
+    pub fn abs(&self) -> Tensor<T> {
+        self._map(program::Op::Abs)
+    }
+
+    pub fn sin(&self) -> Tensor<T> {
+        self._map(program::Op::Sin)
+    }
+
+    pub fn cos(&self) -> Tensor<T> {
+        self._map(program::Op::Cos)
+    }
+
+    /// Sum of every element, as a tensor of shape `[]`.
+    pub fn sum(&self) -> Tensor<T> {
+        self.reshape(&[self.numel()]).sum_axis(0)
+    }
+
+    pub fn mean(&self) -> Tensor<T> {
+        self.reshape(&[self.numel()]).mean_axis(0)
+    }
+
+    pub fn max(&self) -> Tensor<T> {
+        self.reshape(&[self.numel()]).max_axis(0)
+    }
+
+    /// Sums over dimension `axis`, which is removed from the shape.
+    pub fn sum_axis(&self, axis: usize) -> Tensor<T> {
+        self.try_sum_axis(axis)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_sum_axis(&self, axis: usize) -> Result<Tensor<T>, T> {
+        self._reduce(axis, Op::Sum(axis))
+    }
+
+    pub fn mean_axis(&self, axis: usize) -> Tensor<T> {
+        self.try_mean_axis(axis)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_mean_axis(&self, axis: usize) -> Result<Tensor<T>, T> {
+        let n = lit::<T>(self._axis(axis)? as f64);
+        Ok(self.try_sum_axis(axis)? / n)
+    }
+
+    /// Maximum over dimension `axis`, which is removed from the shape. The
+    /// gradient flows to the first maximal element only.
+    pub fn max_axis(&self, axis: usize) -> Tensor<T> {
+        self.try_max_axis(axis)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_max_axis(&self, axis: usize) -> Result<Tensor<T>, T> {
+        self._reduce(axis, Op::Max(axis))
+    }
+
+    pub fn reshape(&self, shape: &[usize]) -> Tensor<T> {
+        self.try_reshape(shape)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_reshape(&self, shape: &[usize]) -> Result<Tensor<T>, T> {
+        if self.numel() != shape.iter().product::<usize>() {
+            return Err(self._mismatch("reshape", shape));
+        }
+        let data = self.data();
+        Ok(Tensor::_node(
+            data,
+            shape.to_vec(),
+            vec![self.clone()],
+            Op::Reshape,
+        ))
+    }
+
+    /// Swaps dimensions `dim0` and `dim1`, copying the data into the new
+    /// row-major order.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Tensor<T> {
+        self.try_transpose(dim0, dim1)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_transpose(&self, dim0: usize, dim1: usize) -> Result<Tensor<T>, T> {
+        self._axis(dim0)?;
+        self._axis(dim1)?;
+        let mut shape = self.0.shape.clone();
+        shape.swap(dim0, dim1);
+
+        let data = {
+            let src = self.0.data.borrow();
+            self._transposed(dim0, dim1)
+                .into_iter()
+                .map(|i| src[i])
+                .collect()
+        };
+        let op = Op::Transpose(dim0, dim1);
+        Ok(Tensor::_node(data, shape, vec![self.clone()], op))
+    }
+
+    /// Matrix product of an `[m, k]` and a `[k, n]` tensor.
+    pub fn matmul(&self, other: &Tensor<T>) -> Tensor<T> {
+        self.try_matmul(other)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_matmul(&self, other: &Tensor<T>) -> Result<Tensor<T>, T> {
+        let (a, b) = (self.shape(), other.shape());
+        if a.len() != 2 || b.len() != 2 || a[1] != b[0] {
+            return Err(self._mismatch("matmul", b));
+        }
+        let (m, k, n) = (a[0], a[1], b[1]);
+
+        let mut data = vec![T::zero(); m * n];
+        {
+            let (a, b) = (self.0.data.borrow(), other.0.data.borrow());
+            for i in 0..m {
+                for p in 0..k {
+                    for j in 0..n {
+                        data[i * n + j] += a[i * k + p] * b[p * n + j];
+                    }
+                }
+            }
+        }
+        Ok(Tensor::_node(
+            data,
+            vec![m, n],
+            vec![self.clone(), other.clone()],
+            Op::Matmul,
+        ))
+    }
+
+    /// `self + other`, failing instead of panicking if the shapes do not
+    /// broadcast. Likewise `try_sub`, `try_mul` and `try_div`.
+    pub fn try_add(&self, other: &Tensor<T>) -> Result<Tensor<T>, T> {
+        self._zip(other, program::Op::Add)
+    }
+
+    pub fn try_sub(&self, other: &Tensor<T>) -> Result<Tensor<T>, T> {
+        self._zip(other, program::Op::Sub)
+    }
+
+    pub fn try_mul(&self, other: &Tensor<T>) -> Result<Tensor<T>, T> {
+        self._zip(other, program::Op::Mul)
+    }
+
+    pub fn try_div(&self, other: &Tensor<T>) -> Result<Tensor<T>, T> {
+        self._zip(other, program::Op::Div)
+    }
+
+    /// Seeds every element of `self` with a gradient of one, i.e.
+    /// differentiates the sum of its elements, and accumulates into the
+    /// `grad` of every tensor it was computed from.
+    pub fn backward(&self) {
+        let topo = self.topo();
+
+        self.0.grad.borrow_mut().fill(T::one());
+
+        for v in topo.iter().rev() {
+            v._backward();
+        }
+    }
+
+    pub fn zero_grad(&self) {
+        for v in self.topo().iter() {
+            v.0.grad.borrow_mut().fill(T::zero());
+        }
+    }
+
+    fn _map(&self, op: program::Op<T>) -> Tensor<T> {
+        let data = self
+            .0
+            .data
+            .borrow()
+            .iter()
+            .map(|&x| op.eval(x, T::zero()))
+            .collect();
+        Tensor::_node(data, self.0.shape.clone(), vec![self.clone()], Op::Map(op))
+    }
+
+    fn _zip(&self, other: &Tensor<T>, op: program::Op<T>) -> Result<Tensor<T>, T> {
+        let shape = broadcast_shape(self.shape(), other.shape())
+            .ok_or_else(|| self._mismatch("broadcast", other.shape()))?;
+        let (ia, ib) = (self._broadcast(&shape), other._broadcast(&shape));
+
+        let data = {
+            let (a, b) = (self.0.data.borrow(), other.0.data.borrow());
+            ia.iter()
+                .zip(&ib)
+                .map(|(&i, &j)| op.eval(a[i], b[j]))
+                .collect()
+        };
+        let prev = vec![self.clone(), other.clone()];
+        Ok(Tensor::_node(data, shape, prev, Op::Map(op)))
+    }
+
+    fn _reduce(&self, axis: usize, _op: Op<T>) -> Result<Tensor<T>, T> {
+        self._axis(axis)?;
+        let (outer, n, inner) = self._split(axis);
+        let src = self.0.data.borrow();
+
+        let mut data = vec![T::zero(); outer * inner];
+        for o in 0..outer {
+            for i in 0..inner {
+                let column = (0..n).map(|j| src[(o * n + j) * inner + i]);
+                data[o * inner + i] = match _op {
+                    Op::Max(_) => column.fold(T::neg_infinity(), T::max),
+                    _ => column.fold(T::zero(), |a, b| a + b),
+                };
+            }
+        }
+
+        let mut shape = self.0.shape.clone();
+        shape.remove(axis);
+        Ok(Tensor::_node(data, shape, vec![self.clone()], _op))
+    }
+
+    fn _node(data: Vec<T>, shape: Vec<usize>, prev: Vec<Tensor<T>>, _op: Op<T>) -> Tensor<T> {
+        let (_prev, _op) = if is_grad_enabled() {
+            (prev, _op)
+        } else {
+            (vec![], Op::None)
+        };
+
+        Tensor(Rc::new(Node {
+            grad: RefCell::new(vec![T::zero(); data.len()]),
+            data: RefCell::new(data),
+            strides: strides(&shape),
+            shape,
+            _prev,
+            _op,
+        }))
+    }
+
+    fn _axis(&self, axis: usize) -> Result<usize, T> {
+        self.0.shape.get(axis).copied().ok_or_else(|| {
+            Error::InvalidOp(format!(
+                "axis {} out of range for shape {:?}",
+                axis, self.0.shape
+            ))
+        })
+    }
+
+    fn _mismatch(&self, op: &'static str, other: &[usize]) -> Error<T> {
+        Error::ShapeMismatch {
+            op,
+            left: self.0.shape.clone(),
+            right: other.to_vec(),
+        }
+    }
+
+    /// The sizes of the dimensions before `axis`, of `axis` and after it.
+    fn _split(&self, axis: usize) -> (usize, usize, usize) {
+        let n = self.0.shape[axis];
+        let outer = self.0.shape[..axis].iter().product();
+        let inner = self.0.shape[axis + 1..].iter().product();
+        (outer, n, inner)
+    }
+
+    /// For each element of a tensor of shape `shape`, the offset of the
+    /// element of `self` broadcast onto it.
+    fn _broadcast(&self, shape: &[usize]) -> Vec<usize> {
+        let pad = shape.len() - self.0.shape.len();
+        let strides: Vec<usize> = (0..shape.len())
+            .map(|d| match d.checked_sub(pad) {
+                Some(d) if self.0.shape[d] != 1 => self.0.strides[d],
+                _ => 0,
+            })
+            .collect();
+
+        offsets(shape, &strides)
+    }
+
+    /// For each element of `self.transpose(dim0, dim1)`, the offset of the
+    /// element of `self` it is a copy of.
+    fn _transposed(&self, dim0: usize, dim1: usize) -> Vec<usize> {
+        let mut shape = self.0.shape.clone();
+        let mut strides = self.0.strides.clone();
+        shape.swap(dim0, dim1);
+        strides.swap(dim0, dim1);
+
+        offsets(&shape, &strides)
+    }
+
+    fn topo(&self) -> Vec<Tensor<T>> {
+        let mut result: Vec<Tensor<T>> = vec![];
+        let mut visited: HashSet<*const Node<T>> = HashSet::new();
+        let mut stack: Vec<(Tensor<T>, bool)> = vec![(self.clone(), false)];
+
+        while let Some((v, expanded)) = stack.pop() {
+            if expanded {
+                result.push(v);
+            } else if visited.insert(Rc::as_ptr(&v.0)) {
+                let children: Vec<(Tensor<T>, bool)> =
+                    v.0._prev.iter().rev().map(|c| (c.clone(), false)).collect();
+
+                stack.push((v, true));
+                stack.extend(children);
+            }
+        }
+
+        result
+    }
+
+    fn _backward(&self) {
+        let grads: Vec<Vec<T>> = {
+            let grad = self.0.grad.borrow();
+            let data = self.0.data.borrow();
+            let prev = &self.0._prev;
+
+            match &self.0._op {
+                Op::Map(op) if prev.len() == 1 => {
+                    let x = prev[0].0.data.borrow();
+
+                    vec![(0..grad.len())
+                        .map(|i| op.partials(x[i], T::zero(), data[i]).0 * grad[i])
+                        .collect()]
+                }
+                Op::Map(op) => {
+                    let (a, b) = (&prev[0], &prev[1]);
+                    let (ia, ib) = (a._broadcast(self.shape()), b._broadcast(self.shape()));
+                    let (x, y) = (a.0.data.borrow(), b.0.data.borrow());
+                    let mut ga = vec![T::zero(); x.len()];
+                    let mut gb = vec![T::zero(); y.len()];
+
+                    for (k, (&i, &j)) in ia.iter().zip(&ib).enumerate() {
+                        let (da, db) = op.partials(x[i], y[j], data[k]);
+                        ga[i] += da * grad[k];
+                        gb[j] += db * grad[k];
+                    }
+                    vec![ga, gb]
+                }
+                Op::Sum(axis) | Op::Max(axis) => {
+                    let (outer, n, inner) = prev[0]._split(*axis);
+                    let x = prev[0].0.data.borrow();
+                    let mut ga = vec![T::zero(); x.len()];
+
+                    for o in 0..outer {
+                        for i in 0..inner {
+                            let k = o * inner + i;
+                            let column = (0..n).map(|j| (o * n + j) * inner + i);
+
+                            if let Op::Max(_) = self.0._op {
+                                if let Some(j) = column.clone().find(|&j| x[j] == data[k]) {
+                                    ga[j] += grad[k];
+                                }
+                            } else {
+                                column.for_each(|j| ga[j] += grad[k]);
+                            }
+                        }
+                    }
+                    vec![ga]
+                }
+                Op::Reshape => {
+                    vec![grad.clone()]
+                }
+                Op::Transpose(dim0, dim1) => {
+                    let mut ga = vec![T::zero(); grad.len()];
+                    for (k, i) in prev[0]._transposed(*dim0, *dim1).into_iter().enumerate() {
+                        ga[i] += grad[k];
+                    }
+                    vec![ga]
+                }
+                Op::Matmul => {
+                    let (m, k, n) = (prev[0].shape()[0], prev[0].shape()[1], prev[1].shape()[1]);
+                    let (a, b) = (prev[0].0.data.borrow(), prev[1].0.data.borrow());
+                    let mut ga = vec![T::zero(); m * k];
+                    let mut gb = vec![T::zero(); k * n];
+
+                    for i in 0..m {
+                        for p in 0..k {
+                            for j in 0..n {
+                                ga[i * k + p] += grad[i * n + j] * b[p * n + j];
+                                gb[p * n + j] += a[i * k + p] * grad[i * n + j];
+                            }
+                        }
+                    }
+                    vec![ga, gb]
+                }
+                Op::None => {
+                    vec![]
+                }
+            }
+        };
+
+        for (v, g) in self.0._prev.iter().zip(grads) {
+            for (acc, g) in v.0.grad.borrow_mut().iter_mut().zip(g) {
+                *acc += g;
+            }
+        }
+    }
+}
+
+/// Row-major strides of a contiguous tensor of shape `shape`.
+fn strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for d in (1..shape.len()).rev() {
+        strides[d - 1] = strides[d] * shape[d];
+    }
+    strides
+}
+
+/// The offsets `sum(index[d] * strides[d])` of every index into `shape`, in
+/// row-major order.
+fn offsets(shape: &[usize], strides: &[usize]) -> Vec<usize> {
+    let numel: usize = shape.iter().product();
+    let mut result = Vec::with_capacity(numel);
+    let mut index = vec![0; shape.len()];
+    let mut offset = 0;
+
+    for _ in 0..numel {
+        result.push(offset);
+        for d in (0..shape.len()).rev() {
+            index[d] += 1;
+            offset += strides[d];
+            if index[d] < shape[d] {
+                break;
+            }
+            offset -= index[d] * strides[d];
+            index[d] = 0;
+        }
+    }
+
+    result
+}
+
+/// The shape two tensors broadcast to: aligned from the last dimension,
+/// each pair of sizes must match or one of them be 1.
+fn broadcast_shape(a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+    let rank = a.len().max(b.len());
+    let dim = |s: &[usize], d: usize| (d + s.len()).checked_sub(rank).map_or(1, |d| s[d]);
+
+    (0..rank)
+        .map(|d| match (dim(a, d), dim(b, d)) {
+            (x, y) if x == y || y == 1 => Some(x),
+            (1, y) => Some(y),
+            _ => None,
+        })
+        .collect()
+}
+
+macro_rules! impl_tensor_ops {
+    ($op:ident, $method:ident, $scalar_op:ident) => {
+        impl<T: Float> $op<&Tensor<T>> for &Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, other: &Tensor<T>) -> Self::Output {
+                self._zip(other, program::Op::$scalar_op)
+                    .unwrap_or_else(|err| panic!("{}", err))
+            }
+        }
+
+        impl<T: Float> $op<Tensor<T>> for Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, other: Tensor<T>) -> Self::Output {
+                self._zip(&other, program::Op::$scalar_op)
+                    .unwrap_or_else(|err| panic!("{}", err))
+            }
+        }
+
+        impl<T: Float> $op<&Tensor<T>> for Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, other: &Tensor<T>) -> Self::Output {
+                self._zip(other, program::Op::$scalar_op)
+                    .unwrap_or_else(|err| panic!("{}", err))
+            }
+        }
+
+        impl<T: Float> $op<Tensor<T>> for &Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, other: Tensor<T>) -> Self::Output {
+                self._zip(&other, program::Op::$scalar_op)
+                    .unwrap_or_else(|err| panic!("{}", err))
+            }
+        }
+
+        impl<T: Float> $op<T> for Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, other: T) -> Self::Output {
+                self._zip(&Tensor::scalar(other), program::Op::$scalar_op)
+                    .unwrap_or_else(|err| panic!("{}", err))
+            }
+        }
+
+        impl<T: Float> $op<T> for &Tensor<T> {
+            type Output = Tensor<T>;
+
+            fn $method(self, other: T) -> Self::Output {
+                self._zip(&Tensor::scalar(other), program::Op::$scalar_op)
+                    .unwrap_or_else(|err| panic!("{}", err))
+            }
+        }
+    };
+}
+
+impl_tensor_ops!(Add, add, Add);
+impl_tensor_ops!(Sub, sub, Sub);
+impl_tensor_ops!(Mul, mul, Mul);
+impl_tensor_ops!(Div, div, Div);
+
+impl<T: Float> Neg for Tensor<T> {
+    type Output = Tensor<T>;
+
+    fn neg(self) -> Self::Output {
+        self._map(program::Op::Neg)
+    }
+}
+
+impl<T: Float> Neg for &Tensor<T> {
+    type Output = Tensor<T>;
+
+    fn neg(self) -> Self::Output {
+        self._map(program::Op::Neg)
+    }
+}
+
+impl<T: Float> Debug for Tensor<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tensor")
+            .field("shape", &self.0.shape)
+            .field("data", &self.0.data.borrow())
+            .field("grad", &self.0.grad.borrow())
+            .finish()
+    }
+}
+
+/// Drops the nodes behind `_prev` one at a time instead of recursively, so
+/// that dropping a deep graph cannot overflow the stack.
+impl<T> Drop for Node<T> {
+    fn drop(&mut self) {
+        let mut stack: Vec<Tensor<T>> = mem::take(&mut self._prev);
+
+        while let Some(node) = stack.pop() {
+            if let Some(mut node) = Rc::into_inner(node.0) {
+                stack.append(&mut node._prev);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::v1::Value;
+
+    #[test]
+    fn strides_are_row_major() {
+        let t: Tensor = Tensor::zeros(&[2, 3, 4]);
+
+        assert_eq!(&[12, 4, 1], t.strides());
+        assert_eq!(24, t.numel());
+    }
+    #[test]
+    fn add_broadcasts_a_row_over_a_matrix() {
+        let a: Tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &[2, 3]);
+        let b: Tensor = Tensor::new(vec![10.0, 20.0, 30.0], &[3]);
+        let c = &a + &b;
+
+        assert_eq!(&[2, 3], c.shape());
+        assert_eq!(vec![11.0, 22.0, 33.0, 14.0, 25.0, 36.0], c.data());
+        assert_eq!(25.0, c.at(&[1, 1]));
+    }
+    #[test]
+    fn backward_sums_gradients_over_broadcast_dimensions() {
+        let a: Tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &[2, 3]);
+        let b: Tensor = Tensor::new(vec![2.0, 3.0], &[2, 1]);
+        let y = (&a * &b).sum();
+
+        y.backward();
+
+        assert_eq!(2.0 * 6.0 + 3.0 * 15.0, y.item());
+        assert_eq!(vec![2.0, 2.0, 2.0, 3.0, 3.0, 3.0], a.grad());
+        assert_eq!(vec![6.0, 15.0], b.grad());
+    }
+    #[test]
+    fn matmul_forward_and_backward() {
+        let a: Tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &[2, 3]);
+        let b: Tensor = Tensor::new(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0], &[3, 2]);
+        let c = a.matmul(&b);
+
+        c.backward();
+
+        assert_eq!(vec![4.0, 5.0, 10.0, 11.0], c.data());
+        assert_eq!(vec![1.0, 1.0, 2.0, 1.0, 1.0, 2.0], a.grad());
+        assert_eq!(vec![5.0, 5.0, 7.0, 7.0, 9.0, 9.0], b.grad());
+    }
+    #[test]
+    fn reductions_over_an_axis() {
+        let a: Tensor = Tensor::new(vec![1.0, 5.0, 3.0, 4.0, 2.0, 6.0], &[2, 3]);
+        let max = a.max_axis(1);
+        let y = max.sum() + a.mean_axis(0).sum();
+
+        y.backward();
+
+        assert_eq!(vec![5.0, 6.0], max.data());
+        assert_eq!(vec![2.5, 3.5, 4.5], a.mean_axis(0).data());
+        assert_eq!(vec![0.5, 1.5, 0.5, 0.5, 0.5, 1.5], a.grad());
+    }
+    #[test]
+    fn transpose_and_reshape_move_gradients_back() {
+        let a: Tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &[2, 3]);
+        let t = a.transpose(0, 1);
+        let w: Tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &[6]);
+        let y = (t.reshape(&[6]) * &w).sum();
+
+        y.backward();
+
+        assert_eq!(&[3, 2], t.shape());
+        assert_eq!(vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0], t.data());
+        assert_eq!(vec![1.0, 3.0, 5.0, 2.0, 4.0, 6.0], a.grad());
+    }
+    #[test]
+    fn elementwise_ops_match_scalar_values() {
+        let xs = [-1.5, 0.25, 2.0];
+        let t: Tensor = Tensor::new(xs.to_vec(), &[3]);
+        (t.tanh() * t.powf(2.0) + t.sigmoid() / 2.0 - &t)
+            .sum()
+            .backward();
+
+        for (i, &x) in xs.iter().enumerate() {
+            let v: Value = Value::new(x);
+            let y = v.clone().tanh() * v.clone().powf(2.0) + v.clone().sigmoid() / 2.0 - &v;
+            y.backward();
+
+            assert!((*v.grad.borrow() - t.grad()[i]).abs() < 1e-12);
+        }
+    }
+    #[test]
+    fn backward_accumulates_through_reused_tensor() {
+        let x: Tensor = Tensor::new(vec![3.0, -1.0], &[2]);
+        let y = (&x * &x + &x).sum();
+
+        y.backward();
+
+        assert_eq!(vec![7.0, -1.0], x.grad());
+    }
+    #[test]
+    fn no_grad_records_no_graph() {
+        let x: Tensor = Tensor::new(vec![1.0, 2.0], &[2]);
+        let y = {
+            let _guard = crate::engine::no_grad();
+            (&x * 2.0).sum()
+        };
+
+        y.backward();
+
+        assert_eq!(6.0, y.item());
+        assert_eq!(vec![0.0, 0.0], x.grad());
+    }
+    #[test]
+    fn try_ops_report_mismatched_shapes() {
+        let a: Tensor = Tensor::zeros(&[2, 3]);
+        let b: Tensor = Tensor::zeros(&[2]);
+
+        assert_eq!(
+            Some(Error::ShapeMismatch {
+                op: "broadcast",
+                left: vec![2, 3],
+                right: vec![2],
+            }),
+            a.try_add(&b).err()
+        );
+        assert!(a.try_matmul(&a).is_err());
+        assert!(a.try_reshape(&[4]).is_err());
+        assert!(matches!(a.try_sum_axis(2), Err(Error::InvalidOp(_))));
+        assert!(Tensor::try_new(vec![1.0, 2.0], &[3]).is_err());
+        assert_eq!(&[3, 2], a.try_transpose(0, 1).unwrap().shape());
+    }
+}
@@ -0,0 +1,117 @@
+/* Generated by microrunn from a program with 2 inputs, 13 params and 1 output; the gradients are those of the sum of the outputs. */
+#include <math.h>
+
+void mlp(const double x[2], double out[1], double grad_x[2], double grad_params[13])
+{
+    static const double params[13] = {
+        0.5312918349127462,
+        0.5472979578041126,
+        0.6401004481524561,
+        0.411842740648469,
+        0.04399938977540653,
+        0.4208072777235066,
+        0.7400501834471496,
+        0.8507590884049223,
+        0.13996610027576048,
+        0.013219575389430268,
+        0.9328236808543411,
+        0.5110878688220093,
+        0.3967427821429063,
+    };
+
+    const double v0 = x[0];
+    const double v1 = x[1];
+    const double v2 = params[0];
+    const double v3 = params[1];
+    const double v4 = params[2];
+    const double v5 = params[3];
+    const double v6 = params[4];
+    const double v7 = params[5];
+    const double v8 = params[6];
+    const double v9 = params[7];
+    const double v10 = params[8];
+    const double v11 = params[9];
+    const double v12 = params[10];
+    const double v13 = params[11];
+    const double v14 = params[12];
+    const double v15 = v2 * v0;
+    const double v16 = v4 + v15;
+    const double v17 = v3 * v1;
+    const double v18 = v16 + v17;
+    const double v19 = tanh(v18);
+    const double v20 = v11 * v19;
+    const double v21 = v14 + v20;
+    const double v22 = v5 * v0;
+    const double v23 = v7 + v22;
+    const double v24 = v6 * v1;
+    const double v25 = v23 + v24;
+    const double v26 = tanh(v25);
+    const double v27 = v12 * v26;
+    const double v28 = v21 + v27;
+    const double v29 = v8 * v0;
+    const double v30 = v10 + v29;
+    const double v31 = v9 * v1;
+    const double v32 = v30 + v31;
+    const double v33 = tanh(v32);
+    const double v34 = v13 * v33;
+    const double v35 = v28 + v34;
+
+    double g[36] = {0};
+    g[35] += 1.0;
+    g[28] += g[35];
+    g[34] += g[35];
+    g[13] += v33 * g[34];
+    g[33] += v13 * g[34];
+    g[32] += (1.0 - (v33 * v33)) * g[33];
+    g[30] += g[32];
+    g[31] += g[32];
+    g[9] += v1 * g[31];
+    g[1] += v9 * g[31];
+    g[10] += g[30];
+    g[29] += g[30];
+    g[8] += v0 * g[29];
+    g[0] += v8 * g[29];
+    g[21] += g[28];
+    g[27] += g[28];
+    g[12] += v26 * g[27];
+    g[26] += v12 * g[27];
+    g[25] += (1.0 - (v26 * v26)) * g[26];
+    g[23] += g[25];
+    g[24] += g[25];
+    g[6] += v1 * g[24];
+    g[1] += v6 * g[24];
+    g[7] += g[23];
+    g[22] += g[23];
+    g[5] += v0 * g[22];
+    g[0] += v5 * g[22];
+    g[14] += g[21];
+    g[20] += g[21];
+    g[11] += v19 * g[20];
+    g[19] += v11 * g[20];
+    g[18] += (1.0 - (v19 * v19)) * g[19];
+    g[16] += g[18];
+    g[17] += g[18];
+    g[3] += v1 * g[17];
+    g[1] += v3 * g[17];
+    g[4] += g[16];
+    g[15] += g[16];
+    g[2] += v0 * g[15];
+    g[0] += v2 * g[15];
+
+    out[0] = v35;
+    grad_x[0] = g[0];
+    grad_x[1] = g[1];
+    grad_params[0] = g[2];
+    grad_params[1] = g[3];
+    grad_params[2] = g[4];
+    grad_params[3] = g[5];
+    grad_params[4] = g[6];
+    grad_params[5] = g[7];
+    grad_params[6] = g[8];
+    grad_params[7] = g[9];
+    grad_params[8] = g[10];
+    grad_params[9] = g[11];
+    grad_params[10] = g[12];
+    grad_params[11] = g[13];
+    grad_params[12] = g[14];
+}
@@ -0,0 +1,99 @@
+/// Generated by microrunn from a program with 2 inputs, 13 params and 1 output; the gradients are those of the sum of the outputs.
+pub fn mlp(x: &[f64; 2]) -> ([f64; 1], [f64; 2], [f64; 13]) {
+    const PARAMS: [f64; 13] = [
+        0.5312918349127462,
+        0.5472979578041126,
+        0.6401004481524561,
+        0.411842740648469,
+        0.04399938977540653,
+        0.4208072777235066,
+        0.7400501834471496,
+        0.8507590884049223,
+        0.13996610027576048,
+        0.013219575389430268,
+        0.9328236808543411,
+        0.5110878688220093,
+        0.3967427821429063,
+    ];
+
+    let v0: f64 = x[0];
+    let v1: f64 = x[1];
+    let v2: f64 = PARAMS[0];
+    let v3: f64 = PARAMS[1];
+    let v4: f64 = PARAMS[2];
+    let v5: f64 = PARAMS[3];
+    let v6: f64 = PARAMS[4];
+    let v7: f64 = PARAMS[5];
+    let v8: f64 = PARAMS[6];
+    let v9: f64 = PARAMS[7];
+    let v10: f64 = PARAMS[8];
+    let v11: f64 = PARAMS[9];
+    let v12: f64 = PARAMS[10];
+    let v13: f64 = PARAMS[11];
+    let v14: f64 = PARAMS[12];
+    let v15: f64 = v2 * v0;
+    let v16: f64 = v4 + v15;
+    let v17: f64 = v3 * v1;
+    let v18: f64 = v16 + v17;
+    let v19: f64 = v18.tanh();
+    let v20: f64 = v11 * v19;
+    let v21: f64 = v14 + v20;
+    let v22: f64 = v5 * v0;
+    let v23: f64 = v7 + v22;
+    let v24: f64 = v6 * v1;
+    let v25: f64 = v23 + v24;
+    let v26: f64 = v25.tanh();
+    let v27: f64 = v12 * v26;
+    let v28: f64 = v21 + v27;
+    let v29: f64 = v8 * v0;
+    let v30: f64 = v10 + v29;
+    let v31: f64 = v9 * v1;
+    let v32: f64 = v30 + v31;
+    let v33: f64 = v32.tanh();
+    let v34: f64 = v13 * v33;
+    let v35: f64 = v28 + v34;
+
+    let mut g = [0.0; 36];
+    g[35] += 1.0;
+    g[28] += g[35];
+    g[34] += g[35];
+    g[13] += v33 * g[34];
+    g[33] += v13 * g[34];
+    g[32] += (1.0 - v33.powi(2)) * g[33];
+    g[30] += g[32];
+    g[31] += g[32];
+    g[9] += v1 * g[31];
+    g[1] += v9 * g[31];
+    g[10] += g[30];
+    g[29] += g[30];
+    g[8] += v0 * g[29];
+    g[0] += v8 * g[29];
+    g[21] += g[28];
+    g[27] += g[28];
+    g[12] += v26 * g[27];
+    g[26] += v12 * g[27];
+    g[25] += (1.0 - v26.powi(2)) * g[26];
+    g[23] += g[25];
+    g[24] += g[25];
+    g[6] += v1 * g[24];
+    g[1] += v6 * g[24];
+    g[7] += g[23];
+    g[22] += g[23];
+    g[5] += v0 * g[22];
+    g[0] += v5 * g[22];
+    g[14] += g[21];
+    g[20] += g[21];
+    g[11] += v19 * g[20];
+    g[19] += v11 * g[20];
+    g[18] += (1.0 - v19.powi(2)) * g[19];
+    g[16] += g[18];
+    g[17] += g[18];
+    g[3] += v1 * g[17];
+    g[1] += v3 * g[17];
+    g[4] += g[16];
+    g[15] += g[16];
+    g[2] += v0 * g[15];
+    g[0] += v2 * g[15];
+
+    ([v35], [g[0], g[1]], [g[2], g[3], g[4], g[5], g[6], g[7], g[8], g[9], g[10], g[11], g[12], g[13], g[14]])
+}
@@ -0,0 +1,80 @@
+/// Generated by microrunn from a program with 2 inputs, 1 param and 2 outputs; the gradients are those of the sum of the outputs.
+pub fn ops(x: &[f64; 2]) -> ([f64; 2], [f64; 2], [f64; 1]) {
+    const PARAMS: [f64; 1] = [
+        0.75,
+    ];
+
+    let v0: f64 = x[0];
+    let v1: f64 = x[1];
+    let v2: f64 = PARAMS[0];
+    let v3: f64 = v0 * v2;
+    let v4: f64 = -1.0;
+    let v5: f64 = v1 * v4;
+    let v6: f64 = v3 + v5;
+    let v7: f64 = v6.tanh();
+    let v8: f64 = v0 / v1;
+    let v9: f64 = v8.exp();
+    let v10: f64 = v7 + v9;
+    let v11: f64 = v1.abs();
+    let v12: f64 = v11.sqrt();
+    let v13: f64 = v12.ln();
+    let v14: f64 = v10 + v13;
+    let v15: f64 = v0.max(0.0);
+    let v16: f64 = if v1 > 0.0 { v1 } else { 0.1 * v1 };
+    let v17: f64 = v15 * v16;
+    let v18: f64 = -2.0;
+    let v19: f64 = v2 * v18;
+    let v20: f64 = 1.0 / (1.0 + (-v19).exp());
+    let v21: f64 = v17 + v20;
+    let v22: f64 = v0.powf(3.0);
+    let v23: f64 = v1.sin();
+    let v24: f64 = v22 + v23;
+    let v25: f64 = v0.cos();
+    let v26: f64 = -1.0;
+    let v27: f64 = v25 * v26;
+    let v28: f64 = v24 + v27;
+    let v29: f64 = v21 * v28;
+
+    let mut g = [0.0; 30];
+    g[14] += 1.0;
+    g[29] += 1.0;
+    g[21] += v28 * g[29];
+    g[28] += v21 * g[29];
+    g[24] += g[28];
+    g[27] += g[28];
+    g[25] += v26 * g[27];
+    g[26] += v25 * g[27];
+    g[0] += -v0.sin() * g[25];
+    g[22] += g[24];
+    g[23] += g[24];
+    g[1] += v1.cos() * g[23];
+    g[0] += (3.0 * v0.powf(2.0)) * g[22];
+    g[17] += g[21];
+    g[20] += g[21];
+    g[19] += (v20 * (1.0 - v20)) * g[20];
+    g[2] += v18 * g[19];
+    g[18] += v2 * g[19];
+    g[15] += v16 * g[17];
+    g[16] += v15 * g[17];
+    g[1] += (if v1 > 0.0 { 1.0 } else { 0.1 }) * g[16];
+    g[0] += (if v0 > 0.0 { 1.0 } else { 0.0 }) * g[15];
+    g[10] += g[14];
+    g[13] += g[14];
+    g[12] += (1.0 / v12) * g[13];
+    g[11] += (0.5 / v12) * g[12];
+    g[1] += (if v1 > 0.0 { 1.0 } else if v1 < 0.0 { -1.0 } else { 0.0 }) * g[11];
+    g[7] += g[10];
+    g[9] += g[10];
+    g[8] += v9 * g[9];
+    g[0] += (1.0 / v1) * g[8];
+    g[1] += (-v0 / v1.powi(2)) * g[8];
+    g[6] += (1.0 - v7.powi(2)) * g[7];
+    g[3] += g[6];
+    g[5] += g[6];
+    g[1] += v4 * g[5];
+    g[4] += v1 * g[5];
+    g[0] += v2 * g[3];
+    g[2] += v0 * g[3];
+
+    ([v14, v29], [g[0], g[1]], [g[2]])
+}
@@ -1,296 +1,863 @@
-use std::cell::RefCell;
+use super::anomaly::{self, is_anomaly_enabled, Phase};
+use super::dot::{self, DotNode};
+use super::formula::Formula;
+use super::grad_mode::is_grad_enabled;
+use super::gradcheck::{self, GradCheck, OpCheck};
+use super::graph::{self, next_id, Label, NodeId, OpKind, Visitor};
+use super::hook::Hooks;
+use super::program::{self, Program, Tracer};
+use super::{lit, sign, Backend, Float};
+use crate::error::Error;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::mem;
 use std::ops::Add;
+use std::ops::Div;
 use std::ops::Mul;
 use std::ops::Neg;
 use std::ops::Sub;
 use std::rc::Rc;
 
-pub struct Value {
-    pub data: f64,
-    pub grad: f64,
-    pub _prev: Vec<Rc<RefCell<Value>>>,
-    _op: Op,
+pub struct Value<T = f64> {
+    pub data: T,
+    pub grad: T,
+    pub _prev: Vec<Rc<RefCell<Value<T>>>>,
+    _op: Op<T>,
+    /// Shared by clones, so the copies `backward` hands back can be matched
+    /// with the node they came from.
+    _id: usize,
+    _total: Rc<Cell<T>>,
+    _hooks: Hooks<T>,
+    _label: Label,
 }
 
 #[derive(Copy, Clone, Debug)]
-enum Op {
+enum Op<T> {
     Add,
     Mul,
-    Powf(f64),
+    Div,
+    Powf(T),
     Tanh,
+    Exp,
+    Ln,
+    Relu,
+    LeakyRelu(T),
+    Sigmoid,
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
     None,
 }
 
-impl Value {
-    pub fn new(data: f64) -> Value {
+impl<T: Float> Value<T> {
+    pub fn new(data: T) -> Value<T> {
         Value {
             data,
-            grad: 0.0,
+            grad: T::zero(),
             _prev: vec![],
             _op: Op::None,
+            _id: next_id(),
+            _total: Rc::new(Cell::new(T::zero())),
+            _hooks: Hooks::new(),
+            _label: Label::new(),
         }
     }
 
-    pub fn tanh(self) -> Value {
-        let data: f64 = self.data.tanh();
-        let grad: f64 = 0.0;
-        let left = Rc::new(RefCell::new(self));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left];
-        let _op: Op = Op::Tanh;
+    /// A new leaf with the same data, so that `self` can be used as a
+    /// constant: `backward` stops at it and never reaches `self`'s graph.
+    pub fn detach(&self) -> Value<T> {
+        Value::new(self.data)
+    }
+
+    pub fn tanh(self) -> Value<T> {
+        let data: T = self.data.tanh();
+        self._unary(data, Op::Tanh)
+    }
+
+    pub fn powf(self, n: T) -> Value<T> {
+        let data: T = self.data.powf(n);
+        self._unary(data, Op::Powf(n))
+    }
+
+    pub fn exp(self) -> Value<T> {
+        let data: T = self.data.exp();
+        self._unary(data, Op::Exp)
+    }
+
+    pub fn ln(self) -> Value<T> {
+        let data: T = self.data.ln();
+        self._unary(data, Op::Ln)
+    }
+
+    pub fn relu(self) -> Value<T> {
+        let data: T = self.data.max(T::zero());
+        self._unary(data, Op::Relu)
+    }
+
+    pub fn leaky_relu(self, alpha: T) -> Value<T> {
+        let data: T = if self.data > T::zero() {
+            self.data
+        } else {
+            alpha * self.data
+        };
+        self._unary(data, Op::LeakyRelu(alpha))
+    }
+
+    pub fn sigmoid(self) -> Value<T> {
+        let data: T = T::one() / (T::one() + (-self.data).exp());
+        self._unary(data, Op::Sigmoid)
+    }
+
+    pub fn sqrt(self) -> Value<T> {
+        let data: T = self.data.sqrt();
+        self._unary(data, Op::Sqrt)
+    }
+
+    pub fn abs(self) -> Value<T> {
+        let data: T = self.data.abs();
+        self._unary(data, Op::Abs)
+    }
+
+    pub fn sin(self) -> Value<T> {
+        let data: T = self.data.sin();
+        self._unary(data, Op::Sin)
+    }
+
+    pub fn cos(self) -> Value<T> {
+        let data: T = self.data.cos();
+        self._unary(data, Op::Cos)
+    }
+
+    fn _unary(self, data: T, _op: Op<T>) -> Value<T> {
+        Value::_node(data, vec![self], _op)
+    }
+
+    fn _binary(self, other: Value<T>, data: T, _op: Op<T>) -> Value<T> {
+        Value::_node(data, vec![self, other], _op)
+    }
+
+    fn _node(data: T, prev: Vec<Value<T>>, _op: Op<T>) -> Value<T> {
+        if !is_grad_enabled() {
+            return Value::new(data);
+        }
+
+        let grad: T = T::zero();
+        let _prev: Vec<Rc<RefCell<Value<T>>>> =
+            prev.into_iter().map(|v| Rc::new(RefCell::new(v))).collect();
 
         Value {
             data,
             grad,
             _prev,
             _op,
+            _id: next_id(),
+            _total: Rc::new(Cell::new(T::zero())),
+            _hooks: Hooks::new(),
+            _label: Label::new(),
         }
     }
 
-    pub fn powf(self, n: f64) -> Value {
-        let data: f64 = self.data.powf(n);
-        let grad: f64 = 0.0;
-        let left = Rc::new(RefCell::new(self));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left];
-        let _op: Op = Op::Powf(n);
+    /// Registers a closure that `backward` calls with this node's gradient
+    /// before passing it on, and whose result replaces it. Since `backward`
+    /// hands every path through the graph its own copy of a node, the hook
+    /// runs once per path, on that path's share of the gradient. A hook
+    /// that is not linear therefore sees the shares, not their sum: clipping
+    /// `x` in `x * x` clips each of its two shares on its own. Use
+    /// `engine::v1`, whose hooks run once on the complete gradient, when that
+    /// matters.
+    pub fn register_hook(&self, hook: impl FnMut(T) -> T + 'static) {
+        self._hooks.push(hook);
+    }
 
-        Value {
-            data,
-            grad,
-            _prev,
-            _op,
+    /// Returns a copy of the graph with every node's gradient filled in,
+    /// one copy per path, and adds each path's gradient to the
+    /// `total_grad()` shared by all clones of the node, so the original
+    /// leaves see the total. The copy is built top-down with an explicit
+    /// stack, so deep graphs cannot overflow the call stack.
+    pub fn backward(self) -> Value<T> {
+        match self.try_backward() {
+            Ok(out) => out,
+            Err(err) => panic!("{}", err),
         }
     }
 
-    pub fn backward(self) -> Value {
-        let mut out = self;
-        out.grad = 1.0;
+    /// Runs `backward`, failing on a node whose `_prev` does not fit its op
+    /// and, in anomaly mode, on non-finite values: then the data of every
+    /// node is checked before any gradient is computed, and every gradient
+    /// as soon as its op's backward rule produces it, and the first
+    /// non-finite one is returned instead of being propagated. On failure
+    /// every `total_grad()` is put back to what it was before the call,
+    /// although hooks that already ran are not undone.
+    pub fn try_backward(self) -> Result<Value<T>, Error<T>> {
+        let check = is_anomaly_enabled();
 
-        fn build_grads(root: &Value) -> Value {
-            let mut result = root.clone()._backward();
-            let mut temp_prev: Vec<Rc<RefCell<Value>>> = vec![];
+        for v in self.parameters() {
+            graph::check_arity(&v.op(), v._prev.len())?;
+        }
+        if check {
+            anomaly::check_forward(&self.nodes().collect::<Vec<graph::Node<T>>>())?;
+        }
 
-            for v in result._prev.iter() {
-                temp_prev.push(Rc::new(RefCell::new(build_grads(&v.borrow().clone()))));
+        let topo = self._topo();
+        let before: Vec<T> = topo.iter().map(|v| v._total.get()).collect();
+        let result = self._sweep(check);
+        if result.is_err() {
+            for (v, total) in topo.iter().zip(before) {
+                v._total.set(total);
             }
+        }
+
+        result
+    }
 
-            result._prev = temp_prev;
+    /// The top-down pass of `try_backward`, stopping at the first error.
+    fn _sweep(self, check: bool) -> Result<Value<T>, Error<T>> {
+        let root = self.clone();
+        let nodes = || root.nodes().collect::<Vec<graph::Node<T>>>();
 
-            result
+        let checked = |v: Value<T>| -> Result<Value<T>, Error<T>> {
+            let v = v._backward();
+            if check {
+                let mut grads = v._prev.iter().map(|c| c.borrow().grad);
+                if let Some(g) = grads.find(|g| !g.is_finite()) {
+                    let anomaly = anomaly::report(&nodes(), v.id(), Phase::Backward, g);
+                    return Err(anomaly.into());
+                }
+            }
+            Ok(v)
+        };
+
+        let mut out = self;
+        out.grad = out._hooks.run(T::one());
+        out._total.set(out._total.get() + out.grad);
+        out = checked(out)?;
+
+        let mut stack: Vec<Rc<RefCell<Value<T>>>> = out._prev.clone();
+        while let Some(node) = stack.pop() {
+            let mut v = node.borrow().clone();
+            v.grad = v._hooks.run(v.grad);
+            v._total.set(v._total.get() + v.grad);
+
+            let v = checked(v)?;
+            stack.extend(v._prev.iter().cloned());
+            *node.borrow_mut() = v;
         }
 
-        out = build_grads(&out);
+        Ok(out)
+    }
 
-        out
+    /// The gradient of every `backward` call since the last `zero_grad`
+    /// with respect to this node, summed over all paths. Unlike the `grad`
+    /// of one copy, it is shared by all clones of the node, such as the
+    /// parameters an `MLP` holds.
+    pub fn total_grad(&self) -> T {
+        self._total.get()
     }
 
-    fn _backward(self) -> Value {
-        let _prev: Vec<Rc<RefCell<Value>>> = match self._op {
-            Op::Add => {
-                let left = &*self._prev[0].borrow();
-                let right = &*self._prev[1].borrow();
+    /// Resets `total_grad()` of every node of the graph behind `self`.
+    pub fn zero_grad(&self) {
+        for v in self.parameters() {
+            v._total.set(T::zero());
+        }
+    }
 
-                let left_grad = self.grad;
-                let right_grad = self.grad;
+    /// Gradients of `self` with respect to each of `wrt`, built as `Value`s
+    /// out of the same ops as the forward pass rather than added to
+    /// `total_grad()`. They stay connected to the graph, so they can be
+    /// differentiated again for second derivatives, Hessian-vector products
+    /// or gradient penalties. Inputs `self` does not depend on get zero, and
+    /// hooks are not run.
+    pub fn gradients(&self, wrt: &[Value<T>]) -> Vec<Value<T>> {
+        let mut grads: HashMap<usize, Value<T>> = HashMap::new();
+        grads.insert(self._id, Value::new(T::one()));
 
-                vec![
-                    Rc::new(RefCell::new(Value {
-                        data: left.data,
-                        grad: left_grad,
-                        _prev: left._prev.clone(),
-                        _op: left._op,
-                    })),
-                    Rc::new(RefCell::new(Value {
-                        data: right.data,
-                        grad: right_grad,
-                        _prev: right._prev.clone(),
-                        _op: right._op,
-                    })),
-                ]
+        for v in self._topo().iter().rev() {
+            let grad = match grads.get(&v._id) {
+                Some(grad) => grad.clone(),
+                None => continue,
+            };
+
+            for (child, g) in v._prev.iter().zip(v._grad_graph(grad)) {
+                let key = child.borrow()._id;
+                let acc = match grads.remove(&key) {
+                    Some(acc) => acc + g,
+                    None => g,
+                };
+                grads.insert(key, acc);
+            }
+        }
+
+        wrt.iter()
+            .map(|w| match grads.get(&w._id) {
+                Some(grad) => grad.clone(),
+                None => Value::new(T::zero()),
+            })
+            .collect()
+    }
+
+    fn _backward(self) -> Value<T> {
+        let grads: Vec<T> = match self._op {
+            Op::Add => {
+                vec![self.grad, self.grad]
             }
             Op::Mul => {
-                let left = &*self._prev[0].borrow();
-                let right = &*self._prev[1].borrow();
+                let left = self._prev[0].borrow().data;
+                let right = self._prev[1].borrow().data;
 
-                let left_grad = right.data * self.grad;
-                let right_grad = left.data * self.grad;
+                vec![right * self.grad, left * self.grad]
+            }
+            Op::Div => {
+                let left = self._prev[0].borrow().data;
+                let right = self._prev[1].borrow().data;
 
                 vec![
-                    Rc::new(RefCell::new(Value {
-                        data: left.data,
-                        grad: left_grad,
-                        _prev: left._prev.clone(),
-                        _op: left._op,
-                    })),
-                    Rc::new(RefCell::new(Value {
-                        data: right.data,
-                        grad: right_grad,
-                        _prev: right._prev.clone(),
-                        _op: right._op,
-                    })),
+                    (T::one() / right) * self.grad,
+                    (-left / right.powi(2)) * self.grad,
                 ]
             }
             Op::Powf(n) => {
-                let left = &*self._prev[0].borrow();
-
-                let left_grad = (n * left.data.powf(n - 1.0)) * self.grad;
+                let left = self._prev[0].borrow().data;
 
-                vec![Rc::new(RefCell::new(Value {
-                    data: left.data,
-                    grad: left_grad,
-                    _prev: left._prev.clone(),
-                    _op: left._op,
-                }))]
+                vec![(n * left.powf(n - T::one())) * self.grad]
             }
             Op::Tanh => {
-                let left = &*self._prev[0].borrow();
+                vec![(T::one() - self.data.powi(2)) * self.grad]
+            }
+            Op::Exp => {
+                vec![self.data * self.grad]
+            }
+            Op::Ln => {
+                let left = self._prev[0].borrow().data;
 
-                let left_grad = (1.0 - left.data.powf(2.0)) * self.grad;
+                vec![(T::one() / left) * self.grad]
+            }
+            Op::Relu => {
+                let left = self._prev[0].borrow().data;
+
+                vec![if left > T::zero() {
+                    self.grad
+                } else {
+                    T::zero()
+                }]
+            }
+            Op::LeakyRelu(alpha) => {
+                let left = self._prev[0].borrow().data;
+
+                vec![if left > T::zero() {
+                    self.grad
+                } else {
+                    alpha * self.grad
+                }]
+            }
+            Op::Sigmoid => {
+                vec![(self.data * (T::one() - self.data)) * self.grad]
+            }
+            Op::Sqrt => {
+                vec![(lit::<T>(0.5) / self.data) * self.grad]
+            }
+            Op::Abs => {
+                let left = self._prev[0].borrow().data;
+
+                vec![sign(left) * self.grad]
+            }
+            Op::Sin => {
+                let left = self._prev[0].borrow().data;
 
-                vec![Rc::new(RefCell::new(Value {
-                    data: left.data,
-                    grad: left_grad,
-                    _prev: left._prev.clone(),
-                    _op: left._op,
-                }))]
+                vec![left.cos() * self.grad]
+            }
+            Op::Cos => {
+                let left = self._prev[0].borrow().data;
+
+                vec![-left.sin() * self.grad]
             }
             Op::None => {
                 vec![]
             }
         };
 
+        let _prev: Vec<Rc<RefCell<Value<T>>>> = self
+            ._prev
+            .iter()
+            .zip(grads)
+            .map(|(v, grad)| {
+                let v = &*v.borrow();
+
+                Rc::new(RefCell::new(Value {
+                    data: v.data,
+                    grad,
+                    _prev: v._prev.clone(),
+                    _op: v._op,
+                    _id: v._id,
+                    _total: v._total.clone(),
+                    _hooks: v._hooks.clone(),
+                    _label: v._label.clone(),
+                }))
+            })
+            .collect();
+
         Value {
             data: self.data,
             grad: self.grad,
             _prev,
             _op: self._op,
+            _id: self._id,
+            _total: self._total.clone(),
+            _hooks: self._hooks.clone(),
+            _label: self._label.clone(),
         }
     }
 
-    pub fn parameters(&self) -> Vec<Value> {
-        let mut result: Vec<Value> = vec![];
+    /// The graph counterpart of `_backward`: maps the upstream gradient
+    /// `grad` to one gradient `Value` per entry of `_prev`.
+    fn _grad_graph(&self, grad: Value<T>) -> Vec<Value<T>> {
+        let out = self.clone();
+        let prev: Vec<Value<T>> = self._prev.iter().map(|v| v.borrow().clone()).collect();
+
+        match self._op {
+            Op::Add => {
+                vec![grad.clone(), grad]
+            }
+            Op::Mul => {
+                vec![&grad * &prev[1], grad * &prev[0]]
+            }
+            Op::Div => {
+                let right = &prev[1] * &prev[1];
+
+                vec![&grad / &prev[1], -(grad * &prev[0]) / right]
+            }
+            Op::Powf(n) => {
+                vec![grad * n * prev[0].clone().powf(n - T::one())]
+            }
+            Op::Tanh => {
+                vec![grad * (1.0 - &out * &out)]
+            }
+            Op::Exp => {
+                vec![grad * out]
+            }
+            Op::Ln => {
+                vec![grad / &prev[0]]
+            }
+            Op::Relu => {
+                let left = prev[0].data;
+
+                vec![
+                    grad * if left > T::zero() {
+                        T::one()
+                    } else {
+                        T::zero()
+                    },
+                ]
+            }
+            Op::LeakyRelu(alpha) => {
+                let left = prev[0].data;
+
+                vec![grad * if left > T::zero() { T::one() } else { alpha }]
+            }
+            Op::Sigmoid => {
+                vec![grad * &out * (1.0 - &out)]
+            }
+            Op::Sqrt => {
+                vec![grad * lit::<T>(0.5) / out]
+            }
+            Op::Abs => {
+                let left = prev[0].data;
 
-        fn go(param: Value, acc: &mut Vec<Value>) {
-            acc.push(param.clone());
-            for child in param._prev.into_iter() {
-                go(child.borrow().clone(), acc);
+                vec![grad * sign(left)]
+            }
+            Op::Sin => {
+                vec![grad * prev[0].clone().cos()]
+            }
+            Op::Cos => {
+                vec![-(grad * prev[0].clone().sin())]
             }
+            Op::None => {
+                vec![]
+            }
+        }
+    }
+
+    /// Applies this node's op to `inputs` in place of its own `_prev`.
+    fn _replay(&self, inputs: Vec<Value<T>>) -> Value<T> {
+        let mut inputs = inputs.into_iter();
+        let mut next = || inputs.next().unwrap();
+
+        match self._op {
+            Op::Add => next() + next(),
+            Op::Mul => next() * next(),
+            Op::Div => next() / next(),
+            Op::Powf(n) => next().powf(n),
+            Op::Tanh => next().tanh(),
+            Op::Exp => next().exp(),
+            Op::Ln => next().ln(),
+            Op::Relu => next().relu(),
+            Op::LeakyRelu(alpha) => next().leaky_relu(alpha),
+            Op::Sigmoid => next().sigmoid(),
+            Op::Sqrt => next().sqrt(),
+            Op::Abs => next().abs(),
+            Op::Sin => next().sin(),
+            Op::Cos => next().cos(),
+            Op::None => self.clone(),
         }
+    }
+
+    /// Compares this node's backward rule with finite differences of its
+    /// forward pass at its current inputs.
+    fn _check(&self) -> Option<OpCheck<T>> {
+        if let Op::None = self._op {
+            return None;
+        }
+
+        let inputs: Vec<T> = self._prev.iter().map(|v| v.borrow().data).collect();
 
-        go(self.clone(), &mut result);
+        gradcheck::check_op(format!("{:?}", self._op), inputs, |x| {
+            let leaves: Vec<Value<T>> = x.iter().map(|&x| Value::new(x)).collect();
+            let out = self._replay(leaves).backward();
+            let grads: Vec<T> = out._prev.iter().map(|v| v.borrow().grad).collect();
+
+            (out.data, grads)
+        })
+    }
+
+    /// Renders the graph behind `self` in Graphviz DOT, one record per node
+    /// with its op, data and grad. Copies of the same node are merged, and
+    /// on a graph returned by `backward` their per-path grads are summed.
+    pub fn to_dot(&self) -> String {
+        let mut nodes: Vec<DotNode<T>> = vec![];
+        let mut index: HashMap<usize, usize> = HashMap::new();
+        let mut edges: Vec<(usize, usize)> = vec![];
+
+        for v in self.parameters() {
+            match index.get(&v._id) {
+                Some(&i) => nodes[i].grad += v.grad,
+                None => {
+                    index.insert(v._id, nodes.len());
+                    nodes.push(DotNode {
+                        id: v._id,
+                        op: v._op_name(),
+                        data: v.data,
+                        grad: v.grad,
+                    });
+                    for child in v._prev.iter() {
+                        edges.push((child.borrow()._id, v._id));
+                    }
+                }
+            }
+        }
+
+        dot::render(&nodes, &edges)
+    }
+
+    fn _op_name(&self) -> Option<String> {
+        match self._op {
+            Op::None => None,
+            op => Some(format!("{:?}", op)),
+        }
+    }
+
+    fn _traced_op(&self) -> program::Op<T> {
+        match self._op {
+            Op::Add => program::Op::Add,
+            Op::Mul => program::Op::Mul,
+            Op::Div => program::Op::Div,
+            Op::Powf(n) => program::Op::Powf(n),
+            Op::Tanh => program::Op::Tanh,
+            Op::Exp => program::Op::Exp,
+            Op::Ln => program::Op::Ln,
+            Op::Relu => program::Op::Relu,
+            Op::LeakyRelu(alpha) => program::Op::LeakyRelu(alpha),
+            Op::Sigmoid => program::Op::Sigmoid,
+            Op::Sqrt => program::Op::Sqrt,
+            Op::Abs => program::Op::Abs,
+            Op::Sin => program::Op::Sin,
+            Op::Cos => program::Op::Cos,
+            Op::None => program::Op::None,
+        }
+    }
+
+    /// Every node of the tree behind `self`, once per path, parents before
+    /// children.
+    pub fn parameters(&self) -> Vec<Value<T>> {
+        let mut result: Vec<Value<T>> = vec![];
+        let mut stack: Vec<Value<T>> = vec![self.clone()];
+
+        while let Some(v) = stack.pop() {
+            stack.extend(v._prev.iter().rev().map(|child| child.borrow().clone()));
+            result.push(v);
+        }
 
         result
     }
-}
 
-impl Add for Value {
-    type Output = Value;
+    pub fn id(&self) -> NodeId {
+        NodeId(self._id)
+    }
 
-    fn add(self, other: Self) -> Self::Output {
-        let data: f64 = self.data + other.data;
-        let grad: f64 = 0.0;
-        let left = Rc::new(RefCell::new(self));
-        let right = Rc::new(RefCell::new(other));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left, right];
-        let _op: Op = Op::Add;
+    pub fn op(&self) -> OpKind<T> {
+        graph::kind(&self._traced_op())
+    }
 
-        Value {
-            data,
-            grad,
-            _prev,
-            _op,
+    /// Every node of the graph behind `self` once, each after the nodes it
+    /// was computed from and `self` last. Copies of a node are merged, and
+    /// on a graph returned by `backward` their per-path grads are summed.
+    pub fn nodes(&self) -> impl Iterator<Item = graph::Node<T>> {
+        let mut grads: HashMap<usize, T> = HashMap::new();
+        for v in self.parameters() {
+            *grads.entry(v._id).or_insert(T::zero()) += v.grad;
         }
+
+        let result: Vec<graph::Node<T>> = self
+            ._topo()
+            .into_iter()
+            .map(|v| graph::Node {
+                id: v.id(),
+                op: v.op(),
+                parents: v._prev.iter().map(|c| c.borrow().id()).collect(),
+                data: v.data,
+                grad: grads[&v._id],
+                label: v._label.get(),
+            })
+            .collect();
+
+        result.into_iter()
     }
-}
 
-impl Add for &Value {
-    type Output = Value;
+    /// One copy of every node of the graph behind `self`, each after the
+    /// nodes it was computed from and `self` last.
+    fn _topo(&self) -> Vec<Value<T>> {
+        let mut result: Vec<Value<T>> = vec![];
+        let mut visited: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<(Value<T>, bool)> = vec![(self.clone(), false)];
 
-    fn add(self, other: Self) -> Self::Output {
-        let data: f64 = self.data + other.data;
-        let grad: f64 = 0.0;
-        let left = Rc::new(RefCell::new(self.clone()));
-        let right = Rc::new(RefCell::new(other.clone()));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left, right];
-        let _op: Op = Op::Add;
+        while let Some((v, expanded)) = stack.pop() {
+            if expanded {
+                result.push(v);
+            } else if visited.insert(v._id) {
+                let children: Vec<(Value<T>, bool)> = v
+                    ._prev
+                    .iter()
+                    .rev()
+                    .map(|c| (c.borrow().clone(), false))
+                    .collect();
 
-        Value {
-            data,
-            grad,
-            _prev,
-            _op,
+                stack.push((v, true));
+                stack.extend(children);
+            }
         }
+
+        result
+    }
+
+    /// Calls `visitor` on each of `nodes()` in turn.
+    pub fn visit(&self, visitor: &mut impl Visitor<T>) {
+        for node in self.nodes() {
+            visitor.visit(&node);
+        }
+    }
+
+    /// Names this node, e.g. `w0` for a weight, for `formula` and
+    /// `nodes()`. Clones of the node share the label.
+    pub fn set_label(&self, label: impl Into<String>) {
+        self._label.set(label.into());
+    }
+
+    pub fn label(&self) -> Option<String> {
+        self._label.get()
+    }
+
+    /// The expression behind `self`, for printing as text or LaTeX or for
+    /// differentiating symbolically. Only labelled leaves appear by name.
+    /// Fails on a graph nested deeper than `formula::MAX_DEPTH`.
+    pub fn formula(&self) -> Result<Formula<T>, Error<T>> {
+        Formula::new(&self.nodes().collect::<Vec<graph::Node<T>>>())
     }
 }
 
-impl Mul for Value {
-    type Output = Value;
+/// Checks the gradients `backward` computes for `f` at `x` against central
+/// finite differences, and every op in the graph of `f` against its own
+/// forward pass so that a wrong backward rule can be pinpointed.
+pub fn gradcheck<T, F>(f: F, x: &[T]) -> GradCheck<T>
+where
+    T: Float,
+    F: Fn(&[Value<T>]) -> Value<T>,
+{
+    let inputs: Vec<Value<T>> = x.iter().map(|&x| Value::new(x)).collect();
+    let out = f(&inputs);
+    let op = out.parameters().iter().find_map(|v| v._check());
 
-    fn mul(self, other: Self) -> Self::Output {
-        let data: f64 = self.data * other.data;
-        let grad: f64 = 0.0;
-        let left = Rc::new(RefCell::new(self));
-        let right = Rc::new(RefCell::new(other));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left, right];
-        let _op: Op = Op::Mul;
+    // `backward` hands back a copy of the graph with one node per path, so
+    // the gradient of an input is the sum over all copies of its leaf.
+    let nodes = out.backward().parameters();
+    let analytic: Vec<T> = inputs
+        .iter()
+        .map(|input| {
+            nodes
+                .iter()
+                .filter(|v| v._id == input._id)
+                .fold(T::zero(), |acc, v| acc + v.grad)
+        })
+        .collect();
 
-        Value {
-            data,
-            grad,
-            _prev,
-            _op,
+    let numeric = gradcheck::numeric(
+        |x| {
+            let inputs: Vec<Value<T>> = x.iter().map(|&x| Value::new(x)).collect();
+            f(&inputs).data
+        },
+        x,
+    );
+
+    GradCheck {
+        inputs: gradcheck::compare(&analytic, &numeric),
+        op,
+    }
+}
+
+/// Flattens the graphs behind `outputs` into a `Program` that can be rerun
+/// on new data for `inputs`, while `params` keep the data they have now
+/// until `Program::set_params`. Copies of a node share one slot, and every
+/// other leaf is a constant.
+pub fn trace<T: Float>(
+    outputs: &[Value<T>],
+    inputs: &[Value<T>],
+    params: &[Value<T>],
+) -> Program<T> {
+    let mut tracer = Tracer::new();
+    let mut expanded: HashSet<usize> = HashSet::new();
+    let mut stack: Vec<(Value<T>, bool)> = inputs
+        .iter()
+        .chain(params)
+        .chain(outputs)
+        .rev()
+        .map(|v| (v.clone(), false))
+        .collect();
+
+    while let Some((v, children_done)) = stack.pop() {
+        if children_done {
+            if !tracer.contains(v._id) {
+                let args: Vec<usize> = v._prev.iter().map(|c| c.borrow()._id).collect();
+                tracer.push(v._id, v._traced_op(), &args, v.data);
+            }
+        } else if !tracer.contains(v._id) && expanded.insert(v._id) {
+            let children: Vec<(Value<T>, bool)> = v
+                ._prev
+                .iter()
+                .rev()
+                .map(|c| (c.borrow().clone(), false))
+                .collect();
+
+            stack.push((v, true));
+            stack.extend(children);
         }
     }
+
+    let ids = |vs: &[Value<T>]| vs.iter().map(|v| v._id).collect::<Vec<usize>>();
+    tracer.finish(&ids(outputs), &ids(inputs), &ids(params))
+}
+
+impl<T: Float> Add for Value<T> {
+    type Output = Value<T>;
+
+    fn add(self, other: Self) -> Self::Output {
+        let data: T = self.data + other.data;
+        self._binary(other, data, Op::Add)
+    }
 }
 
-impl Mul for &Value {
-    type Output = Value;
+impl<T: Float> Mul for Value<T> {
+    type Output = Value<T>;
 
     fn mul(self, other: Self) -> Self::Output {
-        let data: f64 = self.data * other.data;
-        let grad: f64 = 0.0;
-        let left = Rc::new(RefCell::new(self.clone()));
-        let right = Rc::new(RefCell::new(other.clone()));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left, right];
-        let _op: Op = Op::Mul;
+        let data: T = self.data * other.data;
+        self._binary(other, data, Op::Mul)
+    }
+}
 
-        Value {
-            data,
-            grad,
-            _prev,
-            _op,
-        }
+impl<T: Float> Div for Value<T> {
+    type Output = Value<T>;
+
+    fn div(self, other: Self) -> Self::Output {
+        let data: T = self.data / other.data;
+        self._binary(other, data, Op::Div)
     }
 }
 
-impl Sub for Value {
-    type Output = Value;
+impl<T: Float> Sub for Value<T> {
+    type Output = Value<T>;
 
     fn sub(self, other: Self) -> Self::Output {
         self + -other
     }
 }
 
-impl Neg for Value {
+impl<T: Float> Neg for Value<T> {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
-        self * Value::new(-1.0)
+        self * Value::new(-T::one())
     }
 }
 
-impl Clone for Value {
-    fn clone(&self) -> Value {
+impl_ops!();
+
+impl<T: Float> Backend<T> for Value<T> {
+    fn leaf(data: T) -> Value<T> {
+        Value::new(data)
+    }
+
+    fn add(self, other: Self) -> Value<T> {
+        self + other
+    }
+
+    fn mul(self, other: Self) -> Value<T> {
+        self * other
+    }
+
+    fn powf(self, n: T) -> Value<T> {
+        Value::powf(self, n)
+    }
+
+    fn tanh(self) -> Value<T> {
+        Value::tanh(self)
+    }
+
+    fn try_backward(&self) -> Result<Value<T>, Error<T>> {
+        Value::try_backward(self.clone())
+    }
+
+    fn data(&self) -> T {
+        self.data
+    }
+
+    fn grad(&self) -> T {
+        Value::total_grad(self)
+    }
+
+    fn set_data(&mut self, data: T) {
+        self.data = data;
+    }
+
+    fn zero_grad(&self) {
+        Value::zero_grad(self)
+    }
+}
+
+impl<T: Float> Clone for Value<T> {
+    fn clone(&self) -> Value<T> {
         Value {
             data: self.data,
             grad: self.grad,
             _prev: self._prev.clone(),
             _op: self._op,
+            _id: self._id,
+            _total: self._total.clone(),
+            _hooks: self._hooks.clone(),
+            _label: self._label.clone(),
         }
     }
 }
 
-impl Debug for Value {
+impl<T: Float> Debug for Value<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Value")
             .field("data", &self.data)
@@ -299,6 +866,20 @@ impl Debug for Value {
     }
 }
 
+/// Drops the nodes behind `_prev` one at a time instead of recursively, so
+/// that dropping a deep graph cannot overflow the stack.
+impl<T> Drop for Value<T> {
+    fn drop(&mut self) {
+        let mut stack: Vec<Rc<RefCell<Value<T>>>> = mem::take(&mut self._prev);
+
+        while let Some(node) = stack.pop() {
+            if let Some(node) = Rc::into_inner(node) {
+                stack.append(&mut node.into_inner()._prev);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -345,4 +926,267 @@ mod tests {
 
         assert_ne!(0.0, f_back.grad);
     }
+    #[test]
+    fn divide_two_values() {
+        let a = Value::new(3.0);
+        let b = Value::new(-2.0);
+        let result = (a / b).backward();
+
+        assert_eq!(-1.5, result.data);
+        assert_eq!(-0.5, result._prev[0].borrow().grad);
+        assert_eq!(-0.75, result._prev[1].borrow().grad);
+    }
+    #[test]
+    fn exp_and_ln_backward() {
+        let a = Value::new(2.0);
+        let result = a.exp().backward();
+        assert_eq!(2.0_f64.exp(), result._prev[0].borrow().grad);
+
+        let b = Value::new(4.0);
+        let result = b.ln().backward();
+        assert_eq!(0.25, result._prev[0].borrow().grad);
+    }
+    #[test]
+    fn relu_blocks_negative_inputs() {
+        let result = Value::new(-2.0).relu().backward();
+        assert_eq!(0.0, result.data);
+        assert_eq!(0.0, result._prev[0].borrow().grad);
+
+        let result = Value::new(-2.0).leaky_relu(0.1).backward();
+        assert_eq!(-0.2, result.data);
+        assert_eq!(0.1, result._prev[0].borrow().grad);
+    }
+    #[test]
+    fn mixed_scalar_expression() {
+        let x = Value::new(3.0);
+        let result = (2.0 * &x + 1.0) / 7.0 - &x;
+
+        assert_eq!(-2.0, result.data);
+    }
+    #[test]
+    fn compound_assignment_and_sum() {
+        let xs = vec![Value::new(1.0), Value::new(2.0), Value::new(3.0)];
+        let mut total: Value = xs.iter().sum();
+        total *= 2.0;
+        total -= &xs[0];
+
+        assert_eq!(11.0, total.data);
+        assert_eq!(6.0, xs.into_iter().product::<Value>().data);
+    }
+    #[test]
+    fn single_precision_values() {
+        let a: Value<f32> = Value::new(2.0);
+        let b: Value<f32> = Value::new(-3.0);
+        let result = (a * b + 1.0).backward();
+
+        assert_eq!(-5.0_f32, result.data);
+        assert_eq!(1.0_f32, result._prev[0].borrow().grad);
+    }
+    #[test]
+    fn gradcheck_reused_input() {
+        let report = gradcheck(|x| (&x[0] * &x[0] + &x[0] * &x[1]).tanh(), &[0.3, -0.4]);
+
+        assert!(report.passed(), "{:?}", report);
+        assert!(report.inputs[0].abs_error < 1e-8);
+    }
+    #[test]
+    fn to_dot_merges_copies_and_sums_grads() {
+        let x = Value::new(3.0);
+        let y = (&x * &x).backward();
+
+        let dot = y.to_dot();
+
+        assert_eq!(2, dot.matches("[label=").count());
+        assert!(dot.contains("{ data 3.0000 | grad 6.0000 }"));
+        assert!(dot.contains("{ Mul | data 9.0000 | grad 1.0000 }"));
+        assert_eq!(2, dot.matches("n1 -> n0;").count());
+    }
+    #[test]
+    fn nodes_merge_copies_and_sum_grads() {
+        let x = Value::new(3.0);
+        let y = (&x * &x).powf(2.0).backward();
+
+        let nodes: Vec<graph::Node<f64>> = y.nodes().collect();
+
+        assert_eq!(3, nodes.len());
+        assert_eq!(x.id(), nodes[0].id);
+        assert_eq!(OpKind::Leaf, nodes[0].op);
+        assert_eq!(2.0 * 9.0 * 6.0, nodes[0].grad);
+        assert_eq!(vec![x.id(), x.id()], nodes[1].parents);
+        assert_eq!(OpKind::Powf(2.0), nodes[2].op);
+        assert_eq!("Powf(2.0)", nodes[2].op.to_string());
+    }
+    #[test]
+    fn anomaly_mode_reports_instead_of_propagating() {
+        let x: Value = Value::new(0.0);
+        let y = (x.clone().ln() * -1.0).exp();
+
+        let unchecked = y.clone().backward();
+        let _guard = crate::engine::detect_anomaly();
+        let anomaly = match y.try_backward() {
+            Err(Error::Anomaly(anomaly)) => anomaly,
+            other => panic!("expected an anomaly, got {:?}", other),
+        };
+
+        assert!(unchecked.data.is_infinite());
+        assert_eq!(Phase::Forward, anomaly.phase);
+        assert_eq!(OpKind::Ln, anomaly.op);
+        assert_eq!(vec![0.0], anomaly.inputs);
+        assert_eq!(vec![OpKind::Exp, OpKind::Mul, OpKind::Ln], anomaly.path);
+    }
+    #[test]
+    fn failed_backward_leaves_gradients_untouched() {
+        let x: Value = Value::new(0.0);
+        let y = x.clone().sqrt() * 3.0 + &x;
+
+        let _guard = crate::engine::detect_anomaly();
+        let anomaly = match y.clone().try_backward() {
+            Err(Error::Anomaly(anomaly)) => anomaly,
+            other => panic!("expected an anomaly, got {:?}", other),
+        };
+
+        assert_eq!(Phase::Backward, anomaly.phase);
+        assert_eq!(0.0, x.total_grad());
+        assert_eq!(0.0, y.total_grad());
+    }
+    #[test]
+    fn no_grad_records_no_graph() {
+        let a = Value::new(2.0);
+        let b = Value::new(-3.0);
+
+        let result = {
+            let _guard = crate::engine::no_grad();
+            (&a * &b).tanh()
+        };
+
+        assert_eq!((-6.0_f64).tanh(), result.data);
+        assert!(result._prev.is_empty());
+        assert_eq!(2, (&a * &b)._prev.len());
+    }
+    #[test]
+    fn trace_merges_copies() {
+        let x = Value::new(2.0);
+        let y = &x * &x + &x;
+        let mut program = trace(&[y], std::slice::from_ref(&x), &[]);
+
+        program.forward(&[3.0]);
+        program.backward();
+
+        assert_eq!(3, program.len());
+        assert_eq!(vec![12.0], program.outputs());
+        assert_eq!(vec![7.0], program.input_grads());
+    }
+    #[test]
+    fn backward_stops_at_detach() {
+        let x = Value::new(3.0);
+        let y = (&x * &x).tanh();
+        let z = &x * y.detach();
+
+        let grads = z.backward().parameters();
+
+        assert_eq!(9.0_f64.tanh(), grads[1].grad);
+        assert_eq!(3.0, grads[2].grad);
+        assert!(grads[2]._prev.is_empty());
+        assert_eq!(3, grads.len());
+    }
+    #[test]
+    fn hooks_run_once_per_path() {
+        let x = Value::new(3.0);
+        let calls = Rc::new(RefCell::new(0));
+
+        let count = calls.clone();
+        x.register_hook(move |g| {
+            *count.borrow_mut() += 1;
+            g * 2.0
+        });
+        let grads = (&x * &x).backward().parameters();
+
+        assert_eq!(2, *calls.borrow());
+        assert_eq!(6.0, grads[1].grad);
+        assert_eq!(6.0, grads[2].grad);
+    }
+    #[test]
+    fn clipping_hook_on_reused_leaf_clips_each_share() {
+        let x = Value::new(3.0);
+        x.register_hook(|g: f64| g.clamp(-4.0, 4.0));
+
+        let _ = (&x * &x).backward();
+
+        // Each of the two shares is 3.0 and passes the clip, although their
+        // sum of 6.0 would not.
+        assert_eq!(6.0, x.total_grad());
+    }
+    #[test]
+    fn deep_graphs_do_not_overflow_the_stack() {
+        let x = Value::new(1.0);
+        let mut y = Value::new(0.0);
+        for _ in 0..100_000 {
+            y += &x;
+        }
+
+        let program = trace(&[y.clone()], std::slice::from_ref(&x), &[]);
+        let out = y.backward();
+        let dot = out.to_dot();
+
+        assert_eq!(100_002, program.len());
+        assert_eq!(200_001, out.parameters().len());
+        assert!(dot.contains("{ data 1.0000 | grad 100000.0000 }"));
+        drop(out);
+    }
+    #[test]
+    fn formula_shows_labels_given_after_the_graph_was_built() {
+        let x: Value = Value::new(2.0);
+        let y = (x.clone() * x.clone() + 1.0).ln();
+        x.set_label("x");
+
+        let out = y.clone().backward();
+        let dx = y.formula().unwrap().derivative(x.id()).unwrap();
+
+        assert_eq!(Some("x".to_string()), out.nodes().next().unwrap().label);
+        assert_eq!("ln(x*x + 1)", y.formula().unwrap().to_string());
+        assert_eq!("1/(x*x + 1)*(x + x)", dx.to_string());
+        assert_eq!(0.8, x.total_grad());
+    }
+    #[test]
+    fn gradients_can_be_differentiated_again() {
+        let x = Value::new(2.0);
+        let y = x.clone().powf(3.0);
+
+        let dy = y.gradients(std::slice::from_ref(&x)).remove(0);
+        let d2y = dy.gradients(std::slice::from_ref(&x)).remove(0);
+        let d3y = d2y.gradients(std::slice::from_ref(&x)).remove(0);
+
+        assert_eq!(12.0, dy.data);
+        assert_eq!(12.0, d2y.data);
+        assert_eq!(6.0, d3y.data);
+        assert_eq!(0.0, x.total_grad());
+    }
+    #[test]
+    fn gradients_sum_over_shared_nodes() {
+        let x = Value::new(1.5);
+        let y = Value::new(-0.5);
+        let f = &x * &x * &y + y.clone().sin();
+
+        let g = f.gradients(&[x.clone(), y.clone()]);
+        let hxy = g[0].gradients(std::slice::from_ref(&y)).remove(0);
+
+        assert_eq!(2.0 * 1.5 * -0.5, g[0].data);
+        assert_eq!(1.5 * 1.5 + (-0.5_f64).cos(), g[1].data);
+        assert_eq!(2.0 * 1.5, hxy.data);
+    }
+    #[test]
+    fn gradient_penalty_backward_reaches_parameters() {
+        let w = Value::new(0.5);
+        let x = Value::new(0.3);
+        let out = (&w * &x).tanh();
+
+        let dx = out.gradients(std::slice::from_ref(&x)).remove(0);
+        (&dx * &dx).backward();
+
+        let t = (0.5_f64 * 0.3).tanh();
+        let dt = 1.0 - t * t;
+        // dx = w t'(wx), so d/dw dx^2 = 2 dx (t'(wx) - 2 w x t(wx) t'(wx))
+        let expected = 2.0 * (0.5 * dt) * (dt - 2.0 * 0.5 * 0.3 * t * dt);
+        assert!((expected - w.total_grad()).abs() < 1e-12);
+    }
 }
@@ -1,174 +1,855 @@
+use super::anomaly::{self, is_anomaly_enabled, Phase};
+use super::dot::{self, DotNode};
+use super::formula::Formula;
+use super::grad_mode::is_grad_enabled;
+use super::gradcheck::{self, GradCheck, OpCheck};
+use super::graph::{self, next_id, Label, NodeId, OpKind, Visitor};
+use super::hook::Hooks;
+use super::program::{self, Program, Tracer};
+use super::{lit, sign, Backend, Float, Function};
+use crate::error::Error;
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::ops::{Add, Mul, Neg, Sub};
+use std::mem;
+use std::ops::{Add, Div, Mul, Neg, Sub};
 use std::rc::Rc;
 
 #[derive(Clone)]
-pub struct Value {
-    pub data: Rc<RefCell<f64>>,
-    pub grad: Rc<RefCell<f64>>,
-    pub _prev: Vec<Rc<RefCell<Value>>>,
-    _op: Op,
+pub struct Value<T = f64> {
+    pub data: Rc<RefCell<T>>,
+    pub grad: Rc<RefCell<T>>,
+    pub _prev: Vec<Rc<RefCell<Value<T>>>>,
+    _op: Op<T>,
+    _id: usize,
+    _hooks: Hooks<T>,
+    _label: Label,
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Op {
+#[derive(Clone, Debug)]
+enum Op<T> {
     Add,
     Mul,
-    Powf(f64),
+    Div,
+    Powf(T),
     Tanh,
+    Exp,
+    Ln,
+    Relu,
+    LeakyRelu(T),
+    Sigmoid,
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
+    Custom(Rc<dyn Function<T>>),
     None,
 }
 
-impl Value {
-    pub fn new(data: f64) -> Value {
+impl<T: Float> Function<T> for Op<T> {
+    fn forward(&self, inputs: &[T]) -> T {
+        // A custom op may take any number of inputs, even none.
+        let x = match self {
+            Op::Custom(f) => return f.forward(inputs),
+            Op::None => unreachable!("leaves have no forward pass"),
+            _ => inputs[0],
+        };
+
+        match self {
+            Op::Add => x + inputs[1],
+            Op::Mul => x * inputs[1],
+            Op::Div => x / inputs[1],
+            Op::Powf(n) => x.powf(*n),
+            Op::Tanh => x.tanh(),
+            Op::Exp => x.exp(),
+            Op::Ln => x.ln(),
+            Op::Relu => x.max(T::zero()),
+            Op::LeakyRelu(alpha) => {
+                if x > T::zero() {
+                    x
+                } else {
+                    *alpha * x
+                }
+            }
+            Op::Sigmoid => T::one() / (T::one() + (-x).exp()),
+            Op::Sqrt => x.sqrt(),
+            Op::Abs => x.abs(),
+            Op::Sin => x.sin(),
+            Op::Cos => x.cos(),
+            Op::Custom(_) | Op::None => unreachable!("dispatched above"),
+        }
+    }
+
+    fn backward(&self, inputs: &[T], data: T, grad: T) -> Vec<T> {
+        match self {
+            Op::Add => {
+                vec![grad, grad]
+            }
+            Op::Mul => {
+                let (left, right) = (inputs[0], inputs[1]);
+
+                vec![right * grad, left * grad]
+            }
+            Op::Div => {
+                let (left, right) = (inputs[0], inputs[1]);
+
+                vec![(T::one() / right) * grad, (-left / right.powi(2)) * grad]
+            }
+            Op::Powf(n) => {
+                let left = inputs[0];
+
+                vec![(*n * left.powf(*n - T::one())) * grad]
+            }
+            Op::Tanh => {
+                vec![(T::one() - data.powi(2)) * grad]
+            }
+            Op::Exp => {
+                vec![data * grad]
+            }
+            Op::Ln => {
+                let left = inputs[0];
+
+                vec![(T::one() / left) * grad]
+            }
+            Op::Relu => {
+                let left = inputs[0];
+
+                vec![if left > T::zero() { grad } else { T::zero() }]
+            }
+            Op::LeakyRelu(alpha) => {
+                let left = inputs[0];
+
+                vec![if left > T::zero() {
+                    grad
+                } else {
+                    *alpha * grad
+                }]
+            }
+            Op::Sigmoid => {
+                vec![(data * (T::one() - data)) * grad]
+            }
+            Op::Sqrt => {
+                vec![(lit::<T>(0.5) / data) * grad]
+            }
+            Op::Abs => {
+                let left = inputs[0];
+
+                vec![sign(left) * grad]
+            }
+            Op::Sin => {
+                let left = inputs[0];
+
+                vec![left.cos() * grad]
+            }
+            Op::Cos => {
+                let left = inputs[0];
+
+                vec![-left.sin() * grad]
+            }
+            Op::Custom(f) => f.backward(inputs, data, grad),
+            Op::None => {
+                vec![]
+            }
+        }
+    }
+}
+
+impl<T: Float> Value<T> {
+    pub fn new(data: T) -> Value<T> {
         Value {
             data: Rc::new(RefCell::new(data)),
-            grad: Rc::new(RefCell::new(0.0)),
+            grad: Rc::new(RefCell::new(T::zero())),
             _prev: vec![],
             _op: Op::None,
+            _id: next_id(),
+            _hooks: Hooks::new(),
+            _label: Label::new(),
         }
     }
 
-    pub fn tanh(self) -> Value {
-        let data: Rc<RefCell<f64>> = Rc::new(RefCell::new(self.data.borrow().tanh()));
-        let grad: Rc<RefCell<f64>> = Rc::new(RefCell::new(0.0));
-        let left: Rc<RefCell<Value>> = Rc::new(RefCell::new(self));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left];
-        let _op: Op = Op::Tanh;
-
+    /// A new leaf sharing `self`'s data but with its own grad and no
+    /// `_prev`, so that `self` can be used as a constant: `backward` stops at
+    /// it and never reaches `self`'s graph, while later writes to the data
+    /// still show through.
+    pub fn detach(&self) -> Value<T> {
         Value {
-            data,
-            grad,
-            _prev,
-            _op,
+            data: self.data.clone(),
+            grad: Rc::new(RefCell::new(T::zero())),
+            _prev: vec![],
+            _op: Op::None,
+            _id: next_id(),
+            _hooks: Hooks::new(),
+            _label: Label::new(),
         }
     }
 
-    pub fn powf(self, n: f64) -> Value {
-        let data: Rc<RefCell<f64>> = Rc::new(RefCell::new(self.data.borrow().powf(n)));
-        let grad: Rc<RefCell<f64>> = Rc::new(RefCell::new(0.0));
-        let left = Rc::new(RefCell::new(self));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left];
-        let _op: Op = Op::Powf(n);
+    /// Applies a user-defined op to `inputs`, recording it in the graph so
+    /// that `backward` runs its `Function::backward`.
+    pub fn apply<F: Function<T> + 'static>(function: F, inputs: Vec<Value<T>>) -> Value<T> {
+        Value::_node(inputs, Op::Custom(Rc::new(function)))
+    }
+
+    pub fn tanh(self) -> Value<T> {
+        self._unary(Op::Tanh)
+    }
+
+    pub fn powf(self, n: T) -> Value<T> {
+        self._unary(Op::Powf(n))
+    }
+
+    pub fn exp(self) -> Value<T> {
+        self._unary(Op::Exp)
+    }
+
+    pub fn ln(self) -> Value<T> {
+        self._unary(Op::Ln)
+    }
+
+    pub fn relu(self) -> Value<T> {
+        self._unary(Op::Relu)
+    }
+
+    pub fn leaky_relu(self, alpha: T) -> Value<T> {
+        self._unary(Op::LeakyRelu(alpha))
+    }
+
+    pub fn sigmoid(self) -> Value<T> {
+        self._unary(Op::Sigmoid)
+    }
+
+    pub fn sqrt(self) -> Value<T> {
+        self._unary(Op::Sqrt)
+    }
+
+    pub fn abs(self) -> Value<T> {
+        self._unary(Op::Abs)
+    }
+
+    pub fn sin(self) -> Value<T> {
+        self._unary(Op::Sin)
+    }
+
+    pub fn cos(self) -> Value<T> {
+        self._unary(Op::Cos)
+    }
+
+    fn _unary(self, _op: Op<T>) -> Value<T> {
+        Value::_node(vec![self], _op)
+    }
+
+    fn _binary(self, other: Value<T>, _op: Op<T>) -> Value<T> {
+        Value::_node(vec![self, other], _op)
+    }
+
+    fn _node(prev: Vec<Value<T>>, _op: Op<T>) -> Value<T> {
+        let inputs: Vec<T> = prev.iter().map(|v| *v.data.borrow()).collect();
+        let data: T = _op.forward(&inputs);
+
+        if !is_grad_enabled() {
+            return Value::new(data);
+        }
+
+        let data: Rc<RefCell<T>> = Rc::new(RefCell::new(data));
+        let grad: Rc<RefCell<T>> = Rc::new(RefCell::new(T::zero()));
+        let _prev: Vec<Rc<RefCell<Value<T>>>> =
+            prev.into_iter().map(|v| Rc::new(RefCell::new(v))).collect();
 
         Value {
             data,
             grad,
             _prev,
             _op,
+            _id: next_id(),
+            _hooks: Hooks::new(),
+            _label: Label::new(),
         }
     }
 
+    /// Registers a closure that `backward` calls with the gradient this
+    /// call gives the node once it is complete, before passing it on, and
+    /// whose result replaces it. Grads left in `grad` by earlier calls are
+    /// neither seen nor rewritten. Clones of the node share its hooks;
+    /// `gradients` ignores them.
+    pub fn register_hook(&self, hook: impl FnMut(T) -> T + 'static) {
+        self._hooks.push(hook);
+    }
+
     pub fn backward(&self) {
-        *self.grad.borrow_mut() = 1.0;
+        if let Err(err) = self.try_backward() {
+            panic!("{}", err);
+        }
+    }
+
+    /// Runs `backward`, failing on a node whose `_prev` or custom backward
+    /// rule does not fit its op and, in anomaly mode, on non-finite values:
+    /// then the data of every node is checked before any gradient is
+    /// computed, and every gradient as soon as its op's backward rule
+    /// produces it, and the first non-finite one is returned instead of
+    /// being propagated. Each call backpropagates into a scratch buffer and
+    /// only adds the result to `grad` once the sweep succeeds, so grads left
+    /// by an earlier call are accumulated rather than propagated again, and
+    /// on failure no `grad` changes, although hooks that already ran are
+    /// not undone.
+    pub fn try_backward(&self) -> Result<(), Error<T>> {
+        let topo = self.topo();
+        let check = is_anomaly_enabled();
+
+        for v in topo.iter() {
+            graph::check_arity(&v.op(), v._prev.len())?;
+        }
+        if check {
+            anomaly::check_forward(&self.nodes().collect::<Vec<graph::Node<T>>>())?;
+        }
+
+        let grads = self._sweep(&topo, check)?;
+        for v in topo.iter() {
+            if let Some(g) = grads.get(&v._id) {
+                *v.grad.borrow_mut() += *g;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The reverse pass of `try_backward` over `topo`, stopping at the first
+    /// error. Returns this call's gradient of every node, keyed by `_id`.
+    fn _sweep(&self, topo: &[Value<T>], check: bool) -> Result<HashMap<usize, T>, Error<T>> {
+        let nodes = || self.nodes().collect::<Vec<graph::Node<T>>>();
+
+        let mut scratch: HashMap<usize, T> = HashMap::new();
+        scratch.insert(self._id, T::one());
+
+        for v in topo.iter().rev() {
+            let grad = scratch.get(&v._id).copied().unwrap_or_else(T::zero);
+            let grad = v._hooks.run(grad);
+            scratch.insert(v._id, grad);
+
+            let grads = v._grads(grad);
+            if grads.len() != v._prev.len() {
+                return Err(Error::InvalidOp(format!(
+                    "backward of {} gave {} gradients for {} inputs",
+                    v.op(),
+                    grads.len(),
+                    v._prev.len()
+                )));
+            }
+            if check {
+                if let Some(&g) = grads.iter().find(|g| !g.is_finite()) {
+                    let anomaly = anomaly::report(&nodes(), v.id(), Phase::Backward, g);
+                    return Err(anomaly.into());
+                }
+            }
+            for (child, g) in v._prev.iter().zip(grads) {
+                *scratch.entry(child.borrow()._id).or_insert_with(T::zero) += g;
+            }
+        }
+
+        Ok(scratch)
+    }
 
-        fn recurse(root: &Value) {
-            root._backward();
-            for v in root._prev.iter() {
-                recurse(&v.borrow());
+    pub fn zero_grad(&self) {
+        for v in self.topo().iter() {
+            *v.grad.borrow_mut() = T::zero();
+        }
+    }
+
+    /// Gradients of `self` with respect to each of `wrt`, built as `Value`s
+    /// out of the same ops as the forward pass rather than written into
+    /// `grad`. They stay connected to the graph, so they can be
+    /// differentiated again for second derivatives, Hessian-vector products
+    /// or gradient penalties. Inputs `self` does not depend on get zero.
+    pub fn gradients(&self, wrt: &[Value<T>]) -> Vec<Value<T>> {
+        let mut grads: HashMap<usize, Value<T>> = HashMap::new();
+        grads.insert(self._id, Value::new(T::one()));
+
+        for v in self.topo().iter().rev() {
+            let grad = match grads.get(&v._id) {
+                Some(grad) => grad.clone(),
+                None => continue,
+            };
+
+            for (child, g) in v._prev.iter().zip(v._grad_graph(grad)) {
+                let key = child.borrow()._id;
+                let acc = match grads.remove(&key) {
+                    Some(acc) => acc + g,
+                    None => g,
+                };
+                grads.insert(key, acc);
             }
         }
 
-        recurse(self);
+        wrt.iter()
+            .map(|w| match grads.get(&w._id) {
+                Some(grad) => grad.clone(),
+                None => Value::new(T::zero()),
+            })
+            .collect()
+    }
+
+    /// Renders the graph behind `self` in Graphviz DOT, one record per node
+    /// with its op, data and grad. Shared nodes appear once.
+    pub fn to_dot(&self) -> String {
+        let nodes: Vec<graph::Node<T>> = self.nodes().collect();
+
+        let edges: Vec<(usize, usize)> = nodes
+            .iter()
+            .flat_map(|v| v.parents.iter().map(move |&p| (p.0, v.id.0)))
+            .collect();
+        let nodes: Vec<DotNode<T>> = nodes
+            .into_iter()
+            .map(|v| DotNode {
+                id: v.id.0,
+                op: match v.op {
+                    OpKind::Leaf => None,
+                    op => Some(op.to_string()),
+                },
+                data: v.data,
+                grad: v.grad,
+            })
+            .collect();
+
+        dot::render(&nodes, &edges)
+    }
+
+    pub fn id(&self) -> NodeId {
+        NodeId(self._id)
+    }
+
+    pub fn op(&self) -> OpKind<T> {
+        graph::kind(&self._traced_op())
+    }
+
+    /// Every node of the graph behind `self` once, each after the nodes it
+    /// was computed from and `self` last.
+    pub fn nodes(&self) -> impl Iterator<Item = graph::Node<T>> {
+        self.topo().into_iter().map(|v| graph::Node {
+            id: v.id(),
+            op: v.op(),
+            parents: v._prev.iter().map(|c| c.borrow().id()).collect(),
+            data: *v.data.borrow(),
+            grad: *v.grad.borrow(),
+            label: v._label.get(),
+        })
+    }
+
+    /// Calls `visitor` on each of `nodes()` in turn.
+    pub fn visit(&self, visitor: &mut impl Visitor<T>) {
+        for node in self.nodes() {
+            visitor.visit(&node);
+        }
+    }
+
+    /// Names this node, e.g. `w0` for a weight, for `formula` and
+    /// `nodes()`. Clones of the node share the label.
+    pub fn set_label(&self, label: impl Into<String>) {
+        self._label.set(label.into());
+    }
+
+    pub fn label(&self) -> Option<String> {
+        self._label.get()
+    }
+
+    /// The expression behind `self`, for printing as text or LaTeX or for
+    /// differentiating symbolically. Only labelled leaves appear by name.
+    /// Fails on a graph nested deeper than `formula::MAX_DEPTH`.
+    pub fn formula(&self) -> Result<Formula<T>, Error<T>> {
+        Formula::new(&self.nodes().collect::<Vec<graph::Node<T>>>())
+    }
+
+    fn _op_name(&self) -> Option<String> {
+        match &self._op {
+            Op::None => None,
+            Op::Custom(f) => Some(format!("{:?}", f)),
+            op => Some(format!("{:?}", op)),
+        }
+    }
+
+    fn topo(&self) -> Vec<Value<T>> {
+        Value::_topo(std::slice::from_ref(self))
+    }
+
+    /// Every node reachable from `roots`, children before parents, found
+    /// with an explicit stack so that deep graphs cannot overflow the call
+    /// stack.
+    fn _topo(roots: &[Value<T>]) -> Vec<Value<T>> {
+        let mut result: Vec<Value<T>> = vec![];
+        let mut visited: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<(Value<T>, bool)> =
+            roots.iter().rev().map(|v| (v.clone(), false)).collect();
+
+        while let Some((v, expanded)) = stack.pop() {
+            if expanded {
+                result.push(v);
+            } else if visited.insert(v._id) {
+                let children: Vec<(Value<T>, bool)> = v
+                    ._prev
+                    .iter()
+                    .rev()
+                    .map(|child| (child.borrow().clone(), false))
+                    .collect();
+
+                stack.push((v, true));
+                stack.extend(children);
+            }
+        }
+
+        result
+    }
+
+    /// The gradients this node's backward rule passes to each of `_prev`
+    /// when its own gradient is `grad`.
+    fn _grads(&self, grad: T) -> Vec<T> {
+        let data = *self.data.borrow();
+        let inputs: Vec<T> = self
+            ._prev
+            .iter()
+            .map(|v| *v.borrow().data.borrow())
+            .collect();
+
+        self._op.backward(&inputs, data, grad)
+    }
+
+    /// Applies this node's op to `inputs` in place of its own `_prev`.
+    fn _replay(&self, inputs: Vec<Value<T>>) -> Value<T> {
+        match self._op {
+            Op::None => self.clone(),
+            _ => Value::_node(inputs, self._op.clone()),
+        }
+    }
+
+    /// Compares this node's backward rule with finite differences of its
+    /// forward pass at its current inputs.
+    fn _check(&self) -> Option<OpCheck<T>> {
+        let op = self._op_name()?;
+
+        let inputs: Vec<T> = self
+            ._prev
+            .iter()
+            .map(|v| *v.borrow().data.borrow())
+            .collect();
+
+        gradcheck::check_op(op, inputs, |x| {
+            let leaves: Vec<Value<T>> = x.iter().map(|&x| Value::new(x)).collect();
+            let out = self._replay(leaves.clone());
+            out.backward();
+            let grads: Vec<T> = leaves.iter().map(|v| *v.grad.borrow()).collect();
+
+            let data = *out.data.borrow();
+            (data, grads)
+        })
+    }
+
+    fn _traced_op(&self) -> program::Op<T> {
+        match self._op {
+            Op::Add => program::Op::Add,
+            Op::Mul => program::Op::Mul,
+            Op::Div => program::Op::Div,
+            Op::Powf(n) => program::Op::Powf(n),
+            Op::Tanh => program::Op::Tanh,
+            Op::Exp => program::Op::Exp,
+            Op::Ln => program::Op::Ln,
+            Op::Relu => program::Op::Relu,
+            Op::LeakyRelu(alpha) => program::Op::LeakyRelu(alpha),
+            Op::Sigmoid => program::Op::Sigmoid,
+            Op::Sqrt => program::Op::Sqrt,
+            Op::Abs => program::Op::Abs,
+            Op::Sin => program::Op::Sin,
+            Op::Cos => program::Op::Cos,
+            Op::Custom(ref f) => program::Op::Custom(f.clone()),
+            Op::None => program::Op::None,
+        }
     }
 
-    fn _backward(&self) {
+    /// The graph counterpart of `_backward`: maps the upstream gradient
+    /// `grad` to one gradient `Value` per entry of `_prev`.
+    fn _grad_graph(&self, grad: Value<T>) -> Vec<Value<T>> {
+        let out = self.clone();
+        let prev: Vec<Value<T>> = self._prev.iter().map(|v| v.borrow().clone()).collect();
+
         match self._op {
             Op::Add => {
-                *self._prev[0].borrow().grad.borrow_mut() = *self.grad.borrow();
-                *self._prev[1].borrow().grad.borrow_mut() = *self.grad.borrow();
+                vec![grad.clone(), grad]
             }
             Op::Mul => {
-                *self._prev[0].borrow().grad.borrow_mut() =
-                    *self._prev[1].borrow().data.borrow() * *self.grad.borrow();
-                *self._prev[1].borrow().grad.borrow_mut() =
-                    *self._prev[0].borrow().data.borrow() * *self.grad.borrow();
+                vec![&grad * &prev[1], grad * &prev[0]]
+            }
+            Op::Div => {
+                let right = &prev[1] * &prev[1];
+
+                vec![&grad / &prev[1], -(grad * &prev[0]) / right]
             }
             Op::Powf(n) => {
-                *self._prev[0].borrow().grad.borrow_mut() = (n
-                    * (self._prev[0].borrow().data.borrow_mut().powf(n - 1.0)))
-                    * *self.grad.borrow();
+                vec![grad * n * prev[0].clone().powf(n - T::one())]
             }
             Op::Tanh => {
-                *self._prev[0].borrow().grad.borrow_mut() = (1.0
-                    * (self._prev[0].borrow().data.borrow_mut().powf(2.0)))
-                    * *self.grad.borrow();
+                vec![grad * (1.0 - &out * &out)]
+            }
+            Op::Exp => {
+                vec![grad * out]
+            }
+            Op::Ln => {
+                vec![grad / &prev[0]]
+            }
+            Op::Relu => {
+                let left = *prev[0].data.borrow();
+
+                vec![
+                    grad * if left > T::zero() {
+                        T::one()
+                    } else {
+                        T::zero()
+                    },
+                ]
+            }
+            Op::LeakyRelu(alpha) => {
+                let left = *prev[0].data.borrow();
+
+                vec![grad * if left > T::zero() { T::one() } else { alpha }]
+            }
+            Op::Sigmoid => {
+                vec![grad * &out * (1.0 - &out)]
+            }
+            Op::Sqrt => {
+                vec![grad * lit::<T>(0.5) / out]
+            }
+            Op::Abs => {
+                let left = *prev[0].data.borrow();
+
+                vec![grad * sign(left)]
+            }
+            Op::Sin => {
+                vec![grad * prev[0].clone().cos()]
+            }
+            Op::Cos => {
+                vec![-(grad * prev[0].clone().sin())]
+            }
+            Op::Custom(ref f) => {
+                let inputs: Vec<T> = prev.iter().map(|v| *v.data.borrow()).collect();
+                let local = f.backward(&inputs, *out.data.borrow(), T::one());
+
+                local.into_iter().map(|l| &grad * l).collect()
+            }
+            Op::None => {
+                vec![]
             }
-            Op::None => {}
         }
     }
 }
 
-impl Add for Value {
-    type Output = Value;
+/// Checks the gradients `backward` computes for `f` at `x` against central
+/// finite differences, and every op in the graph of `f` against its own
+/// forward pass so that a wrong backward rule can be pinpointed.
+pub fn gradcheck<T, F>(f: F, x: &[T]) -> GradCheck<T>
+where
+    T: Float,
+    F: Fn(&[Value<T>]) -> Value<T>,
+{
+    let inputs: Vec<Value<T>> = x.iter().map(|&x| Value::new(x)).collect();
+    let out = f(&inputs);
+    let op = out.topo().iter().find_map(|v| v._check());
 
-    fn add(self, other: Self) -> Self::Output {
-        let data: Rc<RefCell<f64>> =
-            Rc::new(RefCell::new(*self.data.borrow() + *other.data.borrow()));
-        let grad: Rc<RefCell<f64>> = Rc::new(RefCell::new(0.0));
-        let left = Rc::new(RefCell::new(self));
-        let right = Rc::new(RefCell::new(other));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left, right];
-        let _op: Op = Op::Add;
+    out.backward();
+    let analytic: Vec<T> = inputs.iter().map(|v| *v.grad.borrow()).collect();
 
-        Value {
-            data,
-            grad,
-            _prev,
-            _op,
-        }
+    let numeric = gradcheck::numeric(
+        |x| {
+            let inputs: Vec<Value<T>> = x.iter().map(|&x| Value::new(x)).collect();
+            let data = *f(&inputs).data.borrow();
+            data
+        },
+        x,
+    );
+
+    GradCheck {
+        inputs: gradcheck::compare(&analytic, &numeric),
+        op,
+    }
+}
+
+/// Flattens the graphs behind `outputs` into a `Program` that can be rerun
+/// on new data for `inputs`, while `params` keep the data they have now
+/// until `Program::set_params`. Every other leaf is a constant.
+pub fn trace<T: Float>(
+    outputs: &[Value<T>],
+    inputs: &[Value<T>],
+    params: &[Value<T>],
+) -> Program<T> {
+    let id = |v: &Value<T>| v._id;
+    let mut tracer = Tracer::new();
+
+    let roots: Vec<Value<T>> = inputs
+        .iter()
+        .chain(params)
+        .chain(outputs)
+        .cloned()
+        .collect();
+    for v in Value::_topo(&roots) {
+        let args: Vec<usize> = v._prev.iter().map(|c| id(&c.borrow())).collect();
+        tracer.push(id(&v), v._traced_op(), &args, *v.data.borrow());
+    }
+
+    let ids = |vs: &[Value<T>]| vs.iter().map(id).collect::<Vec<usize>>();
+    tracer.finish(&ids(outputs), &ids(inputs), &ids(params))
+}
+
+impl<T: Float> Add for Value<T> {
+    type Output = Value<T>;
+
+    fn add(self, other: Self) -> Self::Output {
+        self._binary(other, Op::Add)
     }
 }
 
-impl Sub for Value {
-    type Output = Value;
+impl<T: Float> Sub for Value<T> {
+    type Output = Value<T>;
 
     fn sub(self, other: Self) -> Self::Output {
         self + -other
     }
 }
 
-impl Mul for Value {
-    type Output = Value;
+impl<T: Float> Mul for Value<T> {
+    type Output = Value<T>;
 
     fn mul(self, other: Self) -> Self::Output {
-        let data: Rc<RefCell<f64>> =
-            Rc::new(RefCell::new(*self.data.borrow() * *other.data.borrow()));
-        let grad: Rc<RefCell<f64>> = Rc::new(RefCell::new(0.0));
-        let left = Rc::new(RefCell::new(self));
-        let right = Rc::new(RefCell::new(other));
-        let _prev: Vec<Rc<RefCell<Value>>> = vec![left, right];
-        let _op: Op = Op::Mul;
+        self._binary(other, Op::Mul)
+    }
+}
 
-        Value {
-            data,
-            grad,
-            _prev,
-            _op,
-        }
+impl<T: Float> Div for Value<T> {
+    type Output = Value<T>;
+
+    fn div(self, other: Self) -> Self::Output {
+        self._binary(other, Op::Div)
     }
 }
 
-impl Neg for Value {
+impl<T: Float> Neg for Value<T> {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
-        self * Value::new(-1.0)
+        self * Value::new(-T::one())
+    }
+}
+
+impl_ops!();
+
+impl<T: Float> Backend<T> for Value<T> {
+    fn leaf(data: T) -> Value<T> {
+        Value::new(data)
+    }
+
+    fn add(self, other: Self) -> Value<T> {
+        self + other
+    }
+
+    fn mul(self, other: Self) -> Value<T> {
+        self * other
+    }
+
+    fn powf(self, n: T) -> Value<T> {
+        Value::powf(self, n)
+    }
+
+    fn tanh(self) -> Value<T> {
+        Value::tanh(self)
+    }
+
+    fn try_backward(&self) -> Result<Value<T>, Error<T>> {
+        Value::try_backward(self)?;
+        Ok(self.clone())
+    }
+
+    fn data(&self) -> T {
+        *self.data.borrow()
+    }
+
+    fn grad(&self) -> T {
+        *self.grad.borrow()
+    }
+
+    fn set_data(&mut self, data: T) {
+        *self.data.borrow_mut() = data;
+    }
+
+    fn zero_grad(&self) {
+        Value::zero_grad(self)
     }
 }
 
-impl Debug for Value {
+/// Prints the node and, nested inside it, the whole graph behind it, like a
+/// derived `Debug` would but with an explicit stack so that deep graphs can
+/// be printed. Supports `{:#?}`.
+impl<T: Float> Debug for Value<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Value")
-            .field("data", &self.data.borrow())
-            .field("grad", &self.grad.borrow())
-            .field("_prev", &self._prev)
-            .finish()
+        enum Item<T> {
+            Node(Value<T>, usize),
+            Text(String),
+        }
+        let pretty = f.alternate();
+        let pad = |depth: usize| " ".repeat(4 * depth);
+        let mut stack: Vec<Item<T>> = vec![Item::Node(self.clone(), 0)];
+
+        while let Some(item) = stack.pop() {
+            let (v, depth) = match item {
+                Item::Text(text) => {
+                    f.write_str(&text)?;
+                    continue;
+                }
+                Item::Node(v, depth) => (v, depth),
+            };
+            let (data, grad) = (*v.data.borrow(), *v.grad.borrow());
+            let children: Vec<Value<T>> = v._prev.iter().map(|c| c.borrow().clone()).collect();
+
+            if !pretty {
+                write!(f, "Value {{ data: {:?}, grad: {:?}, _prev: [", data, grad)?;
+                stack.push(Item::Text("] }".to_string()));
+                for (i, child) in children.into_iter().enumerate().rev() {
+                    stack.push(Item::Node(child, depth + 1));
+                    if i > 0 {
+                        stack.push(Item::Text(", ".to_string()));
+                    }
+                }
+            } else if children.is_empty() {
+                let (inner, outer) = (pad(depth + 1), pad(depth));
+                write!(
+                    f,
+                    "Value {{\n{inner}data: {data:?},\n{inner}grad: {grad:?},\n{inner}_prev: [],\n{outer}}}"
+                )?;
+            } else {
+                let (inner, outer) = (pad(depth + 1), pad(depth));
+                write!(
+                    f,
+                    "Value {{\n{inner}data: {data:?},\n{inner}grad: {grad:?},\n{inner}_prev: [\n"
+                )?;
+                stack.push(Item::Text(format!("{inner}],\n{outer}}}")));
+                for child in children.into_iter().rev() {
+                    stack.push(Item::Text(",\n".to_string()));
+                    stack.push(Item::Node(child, depth + 2));
+                    stack.push(Item::Text(pad(depth + 2)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Drops the nodes behind `_prev` one at a time instead of recursively, so
+/// that dropping a deep graph cannot overflow the stack.
+impl<T> Drop for Value<T> {
+    fn drop(&mut self) {
+        let mut stack: Vec<Rc<RefCell<Value<T>>>> = mem::take(&mut self._prev);
+
+        while let Some(node) = stack.pop() {
+            if let Some(node) = Rc::into_inner(node) {
+                stack.append(&mut node.into_inner()._prev);
+            }
+        }
     }
 }
 
-impl PartialEq for Value {
+impl<T: Float> PartialEq for Value<T> {
     fn eq(&self, other: &Self) -> bool {
         (*self.data.borrow(), *self.grad.borrow()) == (*other.data.borrow(), *other.grad.borrow())
     }
@@ -224,4 +905,493 @@ mod tests {
 
         assert_ne!(0.0, *f.grad.borrow());
     }
+    #[test]
+    fn backward_accumulates_through_reused_value() {
+        let x = Value::new(3.0);
+        let y = x.clone() * x.clone();
+
+        y.backward();
+
+        assert_eq!(6.0, *x.grad.borrow());
+    }
+    #[test]
+    fn backward_accumulates_through_shared_subexpression() {
+        let a = Value::new(2.0);
+        let b = Value::new(-3.0);
+        let c = a.clone() * b.clone();
+        let d = c.clone() + c.clone() + a.clone();
+
+        d.backward();
+
+        assert_eq!(2.0, *c.grad.borrow());
+        assert_eq!(-5.0, *a.grad.borrow());
+        assert_eq!(4.0, *b.grad.borrow());
+    }
+    #[test]
+    fn roots_sharing_a_node_accumulate_without_repropagating() {
+        let a = Value::new(2.0);
+        let b = Value::new(3.0);
+        let y = &a * &b;
+
+        (&y * 2.0).backward();
+        (&y * 5.0).backward();
+
+        assert_eq!(7.0, *y.grad.borrow());
+        assert_eq!(21.0, *a.grad.borrow());
+        assert_eq!(14.0, *b.grad.borrow());
+
+        let c = (&a * &b) * 2.0;
+        a.zero_grad();
+        c.backward();
+        c.backward();
+
+        assert_eq!(12.0, *a.grad.borrow());
+        assert_eq!(2.0, *c.grad.borrow());
+    }
+    #[test]
+    fn zero_grad_resets_every_node() {
+        let a = Value::new(2.0);
+        let b = Value::new(-3.0);
+        let c = a.clone() * b.clone();
+
+        c.backward();
+        c.zero_grad();
+
+        assert_eq!(0.0, *a.grad.borrow());
+        assert_eq!(0.0, *b.grad.borrow());
+        assert_eq!(0.0, *c.grad.borrow());
+    }
+    #[test]
+    fn sigmoid_backward() {
+        let a = Value::new(0.0);
+        let result = a.clone().sigmoid();
+
+        result.backward();
+
+        assert_eq!(0.5, *result.data.borrow());
+        assert_eq!(0.25, *a.grad.borrow());
+    }
+    #[test]
+    fn divide_two_values() {
+        let a = Value::new(3.0);
+        let b = Value::new(-2.0);
+        let result = a.clone() / b.clone();
+
+        result.backward();
+
+        assert_eq!(-1.5, *result.data.borrow());
+        assert_eq!(-0.5, *a.grad.borrow());
+        assert_eq!(-0.75, *b.grad.borrow());
+    }
+    #[test]
+    fn sin_and_cos_backward() {
+        let a = Value::new(1.0);
+        let result = a.clone().sin() + a.clone().cos();
+
+        result.backward();
+
+        assert_eq!(1.0_f64.cos() - 1.0_f64.sin(), *a.grad.borrow());
+    }
+    #[test]
+    fn ln_abs_and_sqrt_backward() {
+        let a = Value::new(-4.0);
+        let result = a.clone().abs().sqrt().ln();
+
+        result.backward();
+
+        assert_eq!(2.0_f64.ln(), *result.data.borrow());
+        assert_eq!(-0.125, *a.grad.borrow());
+    }
+    #[test]
+    fn mixed_scalar_expression_backward() {
+        let x = Value::new(3.0);
+        let result = 2.0 * &x * &x + 1.0 - &x / 4.0;
+
+        result.backward();
+
+        assert_eq!(18.25, *result.data.borrow());
+        assert_eq!(11.75, *x.grad.borrow());
+    }
+    #[test]
+    fn sum_and_add_assign_backward() {
+        let xs = [Value::new(1.0), Value::new(2.0), Value::new(3.0)];
+        let mut total: Value = xs.iter().sum();
+        total += &xs[0];
+
+        total.backward();
+
+        assert_eq!(7.0, *total.data.borrow());
+        assert_eq!(2.0, *xs[0].grad.borrow());
+        assert_eq!(1.0, *xs[2].grad.borrow());
+    }
+    #[test]
+    fn single_precision_backward() {
+        let x: Value<f32> = Value::new(3.0);
+        let result = 2.0 * &x * &x;
+
+        result.backward();
+
+        assert_eq!(18.0_f32, *result.data.borrow());
+        assert_eq!(12.0_f32, *x.grad.borrow());
+    }
+    #[test]
+    fn gradients_can_be_differentiated_again() {
+        let x = Value::new(2.0);
+        let y = x.clone().powf(3.0);
+
+        let dy = y.gradients(std::slice::from_ref(&x)).remove(0);
+        let d2y = dy.gradients(std::slice::from_ref(&x)).remove(0);
+        let d3y = d2y.gradients(std::slice::from_ref(&x)).remove(0);
+
+        assert_eq!(12.0, *dy.data.borrow());
+        assert_eq!(12.0, *d2y.data.borrow());
+        assert_eq!(6.0, *d3y.data.borrow());
+        assert_eq!(0.0, *x.grad.borrow());
+    }
+    #[test]
+    fn mixed_partials_and_hessian_vector_product() {
+        let x = Value::new(1.5);
+        let y = Value::new(-0.5);
+        let f = &x * &x * &y + y.clone().sin();
+
+        let g = f.gradients(&[x.clone(), y.clone()]);
+        let v = [2.0, 3.0];
+        let gv: Value = g[0].clone() * v[0] + g[1].clone() * v[1];
+        let hv = gv.gradients(&[x.clone(), y.clone()]);
+
+        // H = [[2y, 2x], [2x, -sin(y)]]
+        assert_eq!(2.0 * -0.5 * v[0] + 2.0 * 1.5 * v[1], *hv[0].data.borrow());
+        assert_eq!(
+            2.0 * 1.5 * v[0] - (-0.5_f64).sin() * v[1],
+            *hv[1].data.borrow()
+        );
+    }
+    #[test]
+    fn gradient_penalty_backward_reaches_parameters() {
+        let w = Value::new(0.5);
+        let x = Value::new(0.3);
+        let out = (&w * &x).sigmoid();
+
+        let dx = out.gradients(std::slice::from_ref(&x)).remove(0);
+        (&dx * &dx).backward();
+
+        let s = 1.0 / (1.0 + (-0.5_f64 * 0.3).exp());
+        let ds = s * (1.0 - s);
+        // dx = w s'(wx), so d/dw dx^2 = 2 dx (s'(wx) + w x s'(wx) (1 - 2 s(wx)))
+        let expected = 2.0 * (0.5 * ds) * (ds + 0.5 * 0.3 * ds * (1.0 - 2.0 * s));
+        assert!((expected - *w.grad.borrow()).abs() < 1e-12);
+    }
+    #[test]
+    fn gradients_of_unrelated_input_are_zero() {
+        let x = Value::new(2.0);
+        let z = Value::new(5.0);
+        let y = x.clone().exp();
+
+        let g = y.gradients(&[z]);
+
+        assert_eq!(0.0, *g[0].data.borrow());
+    }
+    #[test]
+    fn gradcheck_tanh() {
+        let report = gradcheck(|x| x[0].clone().tanh(), &[0.5]);
+
+        assert!(report.passed(), "{:?}", report);
+    }
+    #[test]
+    fn gradcheck_every_op() {
+        let report = gradcheck(
+            |x| {
+                let (a, b) = (&x[0], &x[1]);
+                let u = (a * b).tanh() + (a / b).exp() - b.clone().abs().sqrt().ln();
+                let v = a.clone().sigmoid() * b.clone().sin() + a.clone().cos().powf(3.0);
+
+                u.relu() + v.leaky_relu(0.1) + a * a
+            },
+            &[0.7, -1.3],
+        );
+
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(2, report.inputs.len());
+    }
+    #[test]
+    fn to_dot_lists_shared_nodes_once() {
+        let a = Value::new(2.0);
+        let b = Value::new(-3.0);
+        let c = &a * &b;
+        let d = (&c + &c).tanh();
+
+        d.backward();
+        let dot = d.to_dot();
+
+        assert_eq!(5, dot.matches("[label=").count());
+        assert_eq!(5, dot.matches(" -> ").count());
+        assert!(dot.contains("{ Mul | data -6.0000 |"));
+        assert!(dot.contains("{ Tanh | data -1.0000 | grad 1.0000 }"));
+    }
+    #[test]
+    fn no_grad_records_no_graph() {
+        let a = Value::new(2.0);
+        let b = Value::new(-3.0);
+
+        let result = {
+            let _guard = crate::engine::no_grad();
+            (&a + &b).exp() / &b
+        };
+        result.backward();
+
+        assert_eq!((-1.0_f64).exp() / -3.0, *result.data.borrow());
+        assert!(result._prev.is_empty());
+        assert_eq!(0.0, *a.grad.borrow());
+    }
+    #[derive(Debug)]
+    struct Softplus;
+
+    impl Function for Softplus {
+        fn forward(&self, inputs: &[f64]) -> f64 {
+            inputs[0].exp().ln_1p()
+        }
+
+        fn backward(&self, inputs: &[f64], _output: f64, grad: f64) -> Vec<f64> {
+            vec![grad / (1.0 + (-inputs[0]).exp())]
+        }
+    }
+
+    #[derive(Debug)]
+    struct WrongHypot;
+
+    impl Function for WrongHypot {
+        fn forward(&self, inputs: &[f64]) -> f64 {
+            inputs[0].hypot(inputs[1])
+        }
+
+        fn backward(&self, inputs: &[f64], _output: f64, grad: f64) -> Vec<f64> {
+            vec![grad * inputs[0], grad * inputs[1]]
+        }
+    }
+
+    #[derive(Debug)]
+    struct Pi;
+
+    impl Function for Pi {
+        fn forward(&self, _inputs: &[f64]) -> f64 {
+            std::f64::consts::PI
+        }
+
+        fn backward(&self, _inputs: &[f64], _output: f64, _grad: f64) -> Vec<f64> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn custom_op_backward() {
+        let a = Value::new(0.5);
+        let b = Value::apply(Softplus, vec![&a * 2.0]);
+
+        b.backward();
+
+        assert_eq!(1.0_f64.exp().ln_1p(), *b.data.borrow());
+        assert_eq!(2.0 / (1.0 + (-1.0_f64).exp()), *a.grad.borrow());
+        assert!(b.to_dot().contains("{ Softplus | data"));
+    }
+    #[test]
+    fn custom_op_without_inputs() {
+        let c = Value::apply(Pi, vec![]);
+        let y = &c * 2.0;
+
+        y.backward();
+
+        assert_eq!(std::f64::consts::PI, *c.data.borrow());
+        assert_eq!(2.0, *c.grad.borrow());
+    }
+    #[test]
+    fn gradcheck_custom_ops() {
+        let right = gradcheck(
+            |x| Value::apply(Softplus, vec![&x[0] * &x[1]]),
+            &[0.7, -1.3],
+        );
+        let wrong = gradcheck(
+            |x| Value::apply(WrongHypot, vec![x[0].clone(), x[1].clone()]).tanh(),
+            &[0.7, -1.3],
+        );
+
+        assert!(right.passed(), "{:?}", right);
+        assert!(!wrong.passed());
+        assert_eq!("WrongHypot", wrong.op.unwrap().op);
+    }
+    #[test]
+    fn gradients_through_custom_op() {
+        let a = Value::new(0.5);
+        let b = Value::apply(Softplus, vec![&a * &a]);
+
+        let da = b.gradients(std::slice::from_ref(&a));
+
+        let expected = 2.0 * 0.5 / (1.0 + (-0.25_f64).exp());
+        assert!((expected - *da[0].data.borrow()).abs() < 1e-12);
+    }
+    #[test]
+    fn backward_stops_at_detach() {
+        let x = Value::new(3.0);
+        let y = (&x * &x).tanh();
+        let target = y.detach();
+        let z = &x * &target;
+
+        z.backward();
+
+        assert_eq!(9.0_f64.tanh(), *x.grad.borrow());
+        assert_eq!(0.0, *y.grad.borrow());
+        assert_eq!(3.0, *target.grad.borrow());
+
+        *y.data.borrow_mut() = 0.5;
+        assert_eq!(0.5, *target.data.borrow());
+    }
+    #[test]
+    fn hooks_rewrite_gradient_before_it_flows_on() {
+        let a = Value::new(2.0);
+        let b = Value::new(-3.0);
+        let c = &a * &b;
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let log = seen.clone();
+        c.register_hook(move |g| {
+            log.borrow_mut().push(g);
+            g * 10.0
+        });
+        a.register_hook(|g: f64| g.clamp(-1.0, 1.0));
+        (&c + &c).backward();
+
+        assert_eq!(vec![2.0], *seen.borrow());
+        assert_eq!(20.0, *c.grad.borrow());
+        assert_eq!(-1.0, *a.grad.borrow());
+        assert_eq!(40.0, *b.grad.borrow());
+    }
+    #[test]
+    fn hooks_only_see_the_gradient_of_the_current_call() {
+        let x = Value::new(2.0);
+        let z = &x * 3.0;
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let log = seen.clone();
+        x.register_hook(move |g| {
+            log.borrow_mut().push(g);
+            g * 2.0
+        });
+        z.backward();
+        z.backward();
+
+        assert_eq!(vec![3.0, 3.0], *seen.borrow());
+        assert_eq!(12.0, *x.grad.borrow());
+    }
+    #[test]
+    fn deep_graphs_do_not_overflow_the_stack() {
+        let x = Value::new(1.0);
+        let mut y = Value::new(0.0);
+        for _ in 0..100_000 {
+            y += &x;
+        }
+
+        y.backward();
+        let program = trace(&[y.clone()], std::slice::from_ref(&x), &[]);
+        let debug = format!("{:?}", y);
+        y.zero_grad();
+
+        assert_eq!(100_002, program.len());
+        assert_eq!(200_001, debug.matches("Value {").count());
+        assert_eq!(0.0, *x.grad.borrow());
+        drop(y);
+    }
+    #[test]
+    fn nodes_visit_shared_nodes_once_in_topological_order() {
+        let a = Value::new(2.0);
+        let b = Value::apply(Softplus, vec![a.clone()]);
+        let c = (&a * &b).powf(3.0);
+
+        c.backward();
+        let nodes: Vec<graph::Node<f64>> = c.nodes().collect();
+        let mut seen = vec![];
+        c.visit(&mut |node: &graph::Node<f64>| seen.push(node.id));
+
+        assert_eq!(vec![a.id(), b.id(), nodes[2].id, c.id()], seen);
+        assert_eq!(OpKind::Leaf, nodes[0].op);
+        assert_eq!(OpKind::Custom("Softplus".to_string()), nodes[1].op);
+        assert_eq!(vec![a.id(), b.id()], nodes[2].parents);
+        assert_eq!(OpKind::Powf(3.0), c.op());
+        assert_eq!(*a.grad.borrow(), nodes[0].grad);
+    }
+    #[test]
+    fn ids_are_not_reused_after_a_node_is_dropped() {
+        let ids: HashSet<NodeId> = (0..100).map(|_| Value::new(1.0).id()).collect();
+        let x = Value::new(1.0);
+
+        assert_eq!(100, ids.len());
+        assert_eq!(x.id(), x.clone().id());
+        assert_ne!(x.id(), x.detach().id());
+    }
+    #[test]
+    fn anomaly_mode_stops_at_the_first_non_finite_value() {
+        let x = Value::new(-1.0);
+        let y = Value::new(0.0);
+        let forward = (x.clone().powf(0.5).tanh() + &x) * 2.0;
+        let backward = y.clone().sqrt() * 3.0 + &y;
+
+        let _guard = crate::engine::detect_anomaly();
+        let anomaly = |v: Value| match v.try_backward() {
+            Err(Error::Anomaly(anomaly)) => anomaly,
+            other => panic!("expected an anomaly, got {:?}", other),
+        };
+        let forward = anomaly(forward);
+        let backward = anomaly(backward);
+
+        assert_eq!(Phase::Forward, forward.phase);
+        assert_eq!(OpKind::Powf(0.5), forward.op);
+        assert_eq!(vec![-1.0], forward.inputs);
+        assert_eq!(
+            vec![OpKind::Mul, OpKind::Add, OpKind::Tanh, OpKind::Powf(0.5)],
+            forward.path
+        );
+        assert_eq!(0.0, *x.grad.borrow());
+        assert_eq!(Phase::Backward, backward.phase);
+        assert_eq!(OpKind::Sqrt, backward.op);
+        assert_eq!(vec![0.0], backward.inputs);
+        assert_eq!(0.0, *y.grad.borrow());
+    }
+    #[test]
+    fn debug_matches_derived_format() {
+        #[allow(dead_code)]
+        #[derive(Debug)]
+        struct Value {
+            data: f64,
+            grad: f64,
+            _prev: Vec<Value>,
+        }
+        let leaf = |data| Value {
+            data,
+            grad: 0.0,
+            _prev: vec![],
+        };
+        let expected = Value {
+            data: -6.0,
+            grad: 0.0,
+            _prev: vec![leaf(2.0), leaf(-3.0)],
+        };
+
+        let a = super::Value::new(2.0);
+        let b = super::Value::new(-3.0);
+        let c = a * b;
+
+        assert_eq!(format!("{:?}", expected), format!("{:?}", c));
+        assert_eq!(format!("{:#?}", expected), format!("{:#?}", c));
+    }
+    #[test]
+    fn try_backward_rejects_a_custom_op_with_the_wrong_number_of_gradients() {
+        let (a, b) = (Value::new(0.5), Value::new(1.5));
+        let c = Value::apply(Softplus, vec![a.clone(), b.clone()]);
+
+        match c.try_backward() {
+            Err(Error::InvalidOp(reason)) => {
+                assert_eq!("backward of Softplus gave 1 gradients for 2 inputs", reason)
+            }
+            other => panic!("expected an invalid op, got {:?}", other),
+        }
+        assert_eq!(0.0, *a.grad.borrow());
+    }
 }
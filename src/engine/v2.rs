@@ -0,0 +1,462 @@
+use super::{lit, sign, Float};
+use std::cell::{Cell, RefCell};
+use std::fmt::Debug;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A Wengert list: every node of the computation graph lives in one
+/// contiguous vector, in the order it was created. Since a node can only
+/// refer to nodes created before it, the tape is always topologically sorted
+/// and backward is a single reverse sweep.
+pub struct Tape<T: Float = f64> {
+    nodes: RefCell<Vec<Node<T>>>,
+    generation: Cell<usize>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Node<T> {
+    data: T,
+    grad: T,
+    _prev: [usize; 2],
+    _op: Op<T>,
+    generation: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Op<T> {
+    Add,
+    Mul,
+    Div,
+    Powf(T),
+    Tanh,
+    Exp,
+    Ln,
+    Relu,
+    LeakyRelu(T),
+    Sigmoid,
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
+    None,
+}
+
+/// A cheap handle to a node on a [`Tape`]. Using a handle whose node was
+/// dropped by [`Tape::truncate`] panics, even if a newer node has taken its
+/// index since.
+#[derive(Copy, Clone)]
+pub struct Value<'t, T: Float = f64> {
+    tape: &'t Tape<T>,
+    index: usize,
+    generation: usize,
+}
+
+impl<T: Float> Default for Tape<T> {
+    fn default() -> Tape<T> {
+        Tape::new()
+    }
+}
+
+impl<T: Float> Tape<T> {
+    pub fn new() -> Tape<T> {
+        Tape {
+            nodes: RefCell::new(vec![]),
+            generation: Cell::new(0),
+        }
+    }
+
+    pub fn value(&self, data: T) -> Value<'_, T> {
+        self.push(data, [0, 0], Op::None)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.borrow().is_empty()
+    }
+
+    /// Drops every node created after the first `len`, keeping the
+    /// allocation. Nodes pushed afterwards belong to a new generation, so
+    /// handles to dropped nodes are caught instead of reading whichever node
+    /// reuses their index.
+    pub(crate) fn truncate(&self, len: usize) {
+        self.nodes.borrow_mut().truncate(len);
+        self.generation.set(self.generation.get() + 1);
+    }
+
+    pub fn zero_grad(&self) {
+        for node in self.nodes.borrow_mut().iter_mut() {
+            node.grad = T::zero();
+        }
+    }
+
+    fn push(&self, data: T, _prev: [usize; 2], _op: Op<T>) -> Value<'_, T> {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(Node {
+            data,
+            grad: T::zero(),
+            _prev,
+            _op,
+            generation: self.generation.get(),
+        });
+
+        Value {
+            tape: self,
+            index: nodes.len() - 1,
+            generation: self.generation.get(),
+        }
+    }
+
+    /// Backpropagates from `root` into a scratch buffer and adds the result
+    /// to the stored grads, so grads left by an earlier backward on the same
+    /// tape are accumulated rather than propagated again.
+    fn backward(&self, root: usize) {
+        let mut nodes = self.nodes.borrow_mut();
+        let mut grads = vec![T::zero(); root + 1];
+        grads[root] = T::one();
+
+        for i in (0..=root).rev() {
+            let node = nodes[i];
+            let grad = grads[i];
+            let [l, r] = node._prev;
+
+            match node._op {
+                Op::Add => {
+                    grads[l] += grad;
+                    grads[r] += grad;
+                }
+                Op::Mul => {
+                    let left = nodes[l].data;
+                    let right = nodes[r].data;
+
+                    grads[l] += right * grad;
+                    grads[r] += left * grad;
+                }
+                Op::Powf(n) => {
+                    let left = nodes[l].data;
+
+                    grads[l] += (n * left.powf(n - T::one())) * grad;
+                }
+                Op::Div => {
+                    let left = nodes[l].data;
+                    let right = nodes[r].data;
+
+                    grads[l] += (T::one() / right) * grad;
+                    grads[r] += (-left / right.powi(2)) * grad;
+                }
+                Op::Tanh => {
+                    grads[l] += (T::one() - node.data.powi(2)) * grad;
+                }
+                Op::Exp => {
+                    grads[l] += node.data * grad;
+                }
+                Op::Ln => {
+                    let left = nodes[l].data;
+
+                    grads[l] += (T::one() / left) * grad;
+                }
+                Op::Relu => {
+                    if nodes[l].data > T::zero() {
+                        grads[l] += grad;
+                    }
+                }
+                Op::LeakyRelu(alpha) => {
+                    if nodes[l].data > T::zero() {
+                        grads[l] += grad;
+                    } else {
+                        grads[l] += alpha * grad;
+                    }
+                }
+                Op::Sigmoid => {
+                    grads[l] += (node.data * (T::one() - node.data)) * grad;
+                }
+                Op::Sqrt => {
+                    grads[l] += (lit::<T>(0.5) / node.data) * grad;
+                }
+                Op::Abs => {
+                    let left = nodes[l].data;
+
+                    grads[l] += sign(left) * grad;
+                }
+                Op::Sin => {
+                    let left = nodes[l].data;
+
+                    grads[l] += left.cos() * grad;
+                }
+                Op::Cos => {
+                    let left = nodes[l].data;
+
+                    grads[l] += -left.sin() * grad;
+                }
+                Op::None => {}
+            }
+        }
+
+        for (node, grad) in nodes.iter_mut().zip(grads) {
+            node.grad += grad;
+        }
+    }
+}
+
+impl<'t, T: Float> Value<'t, T> {
+    pub fn data(&self) -> T {
+        self._node().data
+    }
+
+    pub fn grad(&self) -> T {
+        self._node().grad
+    }
+
+    pub fn set_data(&self, data: T) {
+        self._node();
+        self.tape.nodes.borrow_mut()[self.index].data = data;
+    }
+
+    /// This handle's node, panicking if it was dropped from the tape.
+    fn _node(&self) -> Node<T> {
+        match self.tape.nodes.borrow().get(self.index) {
+            Some(node) if node.generation == self.generation => *node,
+            _ => panic!("value was dropped from its tape by a reset"),
+        }
+    }
+
+    pub fn tanh(self) -> Value<'t, T> {
+        self._unary(self.data().tanh(), Op::Tanh)
+    }
+
+    pub fn powf(self, n: T) -> Value<'t, T> {
+        self._unary(self.data().powf(n), Op::Powf(n))
+    }
+
+    pub fn exp(self) -> Value<'t, T> {
+        self._unary(self.data().exp(), Op::Exp)
+    }
+
+    pub fn ln(self) -> Value<'t, T> {
+        self._unary(self.data().ln(), Op::Ln)
+    }
+
+    pub fn relu(self) -> Value<'t, T> {
+        self._unary(self.data().max(T::zero()), Op::Relu)
+    }
+
+    pub fn leaky_relu(self, alpha: T) -> Value<'t, T> {
+        let x = self.data();
+        let data = if x > T::zero() { x } else { alpha * x };
+        self._unary(data, Op::LeakyRelu(alpha))
+    }
+
+    pub fn sigmoid(self) -> Value<'t, T> {
+        self._unary(T::one() / (T::one() + (-self.data()).exp()), Op::Sigmoid)
+    }
+
+    pub fn sqrt(self) -> Value<'t, T> {
+        self._unary(self.data().sqrt(), Op::Sqrt)
+    }
+
+    pub fn abs(self) -> Value<'t, T> {
+        self._unary(self.data().abs(), Op::Abs)
+    }
+
+    pub fn sin(self) -> Value<'t, T> {
+        self._unary(self.data().sin(), Op::Sin)
+    }
+
+    pub fn cos(self) -> Value<'t, T> {
+        self._unary(self.data().cos(), Op::Cos)
+    }
+
+    fn _unary(self, data: T, _op: Op<T>) -> Value<'t, T> {
+        self.tape.push(data, [self.index, self.index], _op)
+    }
+
+    fn _binary(self, other: Value<'t, T>, data: T, _op: Op<T>) -> Value<'t, T> {
+        assert!(
+            std::ptr::eq(self.tape, other.tape),
+            "cannot combine values from different tapes"
+        );
+        self.tape.push(data, [self.index, other.index], _op)
+    }
+
+    pub fn backward(&self) {
+        self._node();
+        self.tape.backward(self.index);
+    }
+}
+
+impl<'t, T: Float> Add for Value<'t, T> {
+    type Output = Value<'t, T>;
+
+    fn add(self, other: Self) -> Self::Output {
+        self._binary(other, self.data() + other.data(), Op::Add)
+    }
+}
+
+impl<'t, T: Float> Sub for Value<'t, T> {
+    type Output = Value<'t, T>;
+
+    fn sub(self, other: Self) -> Self::Output {
+        self + -other
+    }
+}
+
+impl<'t, T: Float> Mul for Value<'t, T> {
+    type Output = Value<'t, T>;
+
+    fn mul(self, other: Self) -> Self::Output {
+        self._binary(other, self.data() * other.data(), Op::Mul)
+    }
+}
+
+impl<'t, T: Float> Div for Value<'t, T> {
+    type Output = Value<'t, T>;
+
+    fn div(self, other: Self) -> Self::Output {
+        self._binary(other, self.data() / other.data(), Op::Div)
+    }
+}
+
+impl<'t, T: Float> Neg for Value<'t, T> {
+    type Output = Value<'t, T>;
+
+    fn neg(self) -> Self::Output {
+        self * self.tape.value(-T::one())
+    }
+}
+
+impl<T: Float> Debug for Value<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Value")
+            .field("data", &self.data())
+            .field("grad", &self.grad())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_two_values() {
+        let tape: Tape = Tape::new();
+        let a = tape.value(2.0);
+        let b = tape.value(-3.0);
+        let result = a + b;
+        assert_eq!(result.data(), -1.0);
+    }
+    #[test]
+    fn multiply_two_values() {
+        let tape: Tape = Tape::new();
+        let a = tape.value(2.0);
+        let b = tape.value(-3.0);
+        let result = a * b;
+        assert_eq!(result.data(), -6.0);
+    }
+    #[test]
+    fn tanh_one_value() {
+        let tape: Tape = Tape::new();
+        let a = tape.value(2.0);
+        let result = a.tanh();
+        let offset = 0.000009;
+        assert!((0.96402 + offset) > result.data() && result.data() > (0.96402 - offset))
+    }
+    #[test]
+    fn feed_forward() {
+        let tape: Tape = Tape::new();
+        let a = tape.value(2.0);
+        let b = tape.value(-3.0);
+        let c = tape.value(10.0);
+        let d = a * b;
+        let e = d + c;
+        let f = e.tanh();
+
+        f.backward();
+
+        let dtanh = 1.0 - f.data().powf(2.0);
+        assert_eq!(1.0, f.grad());
+        assert_eq!(dtanh * -3.0, a.grad());
+        assert_eq!(dtanh * 2.0, b.grad());
+        assert_eq!(dtanh, c.grad());
+    }
+    #[test]
+    fn backward_accumulates_through_reused_value() {
+        let tape: Tape = Tape::new();
+        let x = tape.value(3.0);
+        let y = x * x + x;
+
+        y.backward();
+
+        assert_eq!(7.0, x.grad());
+    }
+    #[test]
+    fn truncate_keeps_leading_nodes() {
+        let tape: Tape = Tape::new();
+        let a = tape.value(2.0);
+        let mark = tape.len();
+        let _ = a * a;
+
+        tape.truncate(mark);
+
+        assert_eq!(1, tape.len());
+        assert_eq!(2.0, a.data());
+    }
+    #[test]
+    #[should_panic(expected = "dropped from its tape")]
+    fn truncated_handles_do_not_alias_new_nodes() {
+        let tape: Tape = Tape::new();
+        let _ = tape.value(2.0);
+        let mark = tape.len();
+        let stale = tape.value(7.0);
+
+        tape.truncate(mark);
+        let _ = tape.value(-99.0);
+
+        stale.data();
+    }
+    #[test]
+    fn extended_ops_backward() {
+        let tape: Tape = Tape::new();
+        let a = tape.value(-4.0);
+        let b = tape.value(2.0);
+        let y = a.abs().sqrt() / b + b.relu() + a.leaky_relu(0.5) + b.exp().ln();
+
+        y.backward();
+
+        assert_eq!(1.0 + 2.0 - 2.0 + 2.0, y.data());
+        assert_eq!(-0.125 + 0.5, a.grad());
+        assert_eq!(-0.5 + 1.0 + 1.0, b.grad());
+    }
+    #[test]
+    fn single_precision_tape() {
+        let tape: Tape<f32> = Tape::new();
+        let x = tape.value(3.0);
+        let y = x * x;
+
+        y.backward();
+
+        assert_eq!(6.0_f32, x.grad());
+    }
+    #[test]
+    fn second_backward_on_shared_tape_does_not_double_count() {
+        let tape: Tape = Tape::new();
+        let x = tape.value(3.0);
+        let l1 = x * x;
+        let l2 = x + x;
+
+        l1.backward();
+        l2.backward();
+
+        assert_eq!(6.0 + 2.0, x.grad());
+        assert_eq!(1.0, l1.grad());
+    }
+    #[test]
+    #[should_panic(expected = "different tapes")]
+    fn values_from_different_tapes_cannot_be_combined() {
+        let a: Tape = Tape::new();
+        let b: Tape = Tape::new();
+
+        let _ = a.value(1.0) + b.value(2.0);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::engine::{Anomaly, Float};
+use std::fmt::{self, Display};
+
+/// Everything the `try_` methods of `engine` and `nn` can fail with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error<T = f64> {
+    /// `found` values were passed where `expected` were needed, such as
+    /// features to a `Neuron` or targets to `MLP::try_loss`.
+    LengthMismatch { expected: usize, found: usize },
+    /// Tensors of shapes `left` and `right` that `op` cannot combine.
+    ShapeMismatch {
+        op: &'static str,
+        left: Vec<usize>,
+        right: Vec<usize>,
+    },
+    /// A loss or training step over no samples.
+    EmptyDataset,
+    /// An op applied to operands it is not defined for, such as a node with
+    /// the wrong number of `_prev` or an axis a tensor does not have.
+    InvalidOp(String),
+    /// A non-finite value caught in anomaly mode.
+    Anomaly(Anomaly<T>),
+    /// A graph of no nodes where one node at least was needed.
+    EmptyGraph,
+    /// A graph nested `depth` levels deep, where at most `max` are
+    /// supported, such as by `Formula`.
+    TooDeep { depth: usize, max: usize },
+    /// An expression of `size` nodes once written out, where at most `max`
+    /// are supported, such as by `Formula`.
+    TooLarge { size: usize, max: usize },
+}
+
+pub type Result<R, T = f64> = std::result::Result<R, Error<T>>;
+
+/// Checks that `found` values were passed where `expected` are needed.
+pub(crate) fn check_len<T>(expected: usize, found: usize) -> Result<(), T> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(Error::LengthMismatch { expected, found })
+    }
+}
+
+impl<T> From<Anomaly<T>> for Error<T> {
+    fn from(anomaly: Anomaly<T>) -> Error<T> {
+        Error::Anomaly(anomaly)
+    }
+}
+
+impl<T: Float> Display for Error<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::LengthMismatch { expected, found } => {
+                write!(f, "expected {} values, found {}", expected, found)
+            }
+            Error::ShapeMismatch { op, left, right } => {
+                write!(f, "{} cannot combine shapes {:?} and {:?}", op, left, right)
+            }
+            Error::EmptyDataset => f.write_str("empty dataset"),
+            Error::InvalidOp(reason) => write!(f, "invalid op: {}", reason),
+            Error::Anomaly(anomaly) => write!(f, "anomaly: {}", anomaly),
+            Error::EmptyGraph => f.write_str("empty graph"),
+            Error::TooDeep { depth, max } => {
+                write!(
+                    f,
+                    "graph is {} levels deep, at most {} are supported",
+                    depth, max
+                )
+            }
+            Error::TooLarge { size, max } => {
+                write!(
+                    f,
+                    "expression has {} nodes, at most {} are supported",
+                    size, max
+                )
+            }
+        }
+    }
+}
+
+impl<T: Float> std::error::Error for Error<T> {}
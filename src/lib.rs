@@ -1,2 +1,5 @@
 pub mod engine;
+pub mod error;
 pub mod nn;
+
+pub use error::{Error, Result};
@@ -17,5 +17,13 @@ fn main() {
     let model: MLP = MLP::new(2, vec![3, 3, 1]);
     let mut loss: Value = model.loss(inputs, targets);
     loss = loss.backward();
-    println!("{:#?}", loss.parameters());
+
+    if std::env::args().any(|arg| arg == "--dot") {
+        print!("{}", loss.to_dot());
+        return;
+    }
+
+    for p in model.parameters() {
+        println!("data: {:.4} | grad: {:.4}", p.data, p.total_grad());
+    }
 }
@@ -0,0 +1,438 @@
+use crate::engine::dual::Dual;
+use crate::engine::{lit, Backend, Float};
+use crate::error::{check_len, Error, Result};
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::marker::PhantomData;
+
+/// The generator every model starts from, so that runs are reproducible.
+pub(crate) fn seeded() -> StdRng {
+    let seed = 42; // Choose a seed value
+    StdRng::seed_from_u64(seed)
+}
+
+/// A neuron over the values of any engine implementing [`Backend`].
+#[derive(Clone, Debug)]
+pub struct Neuron<T: Float, V: Backend<T>> {
+    weights: Vec<V>,
+    bias: V,
+    non_lin: bool,
+    _scalar: PhantomData<T>,
+}
+
+impl<T: Float, V: Backend<T>> Neuron<T, V> {
+    pub fn new(nin: usize, non_lin: bool) -> Neuron<T, V> {
+        Neuron::with_rng(nin, non_lin, &mut seeded())
+    }
+
+    /// Like `new`, drawing the initial weights and bias from `rng`, so that
+    /// neurons built from one generator differ.
+    pub fn with_rng(nin: usize, non_lin: bool, rng: &mut impl Rng) -> Neuron<T, V> {
+        let generator = Uniform::from(0.01..=1.00);
+
+        Neuron {
+            weights: (0..nin)
+                .map(|_| V::leaf(lit(generator.sample(rng))))
+                .collect(),
+            bias: V::leaf(lit(generator.sample(rng))),
+            non_lin,
+            _scalar: PhantomData,
+        }
+    }
+
+    pub fn call(&self, x: &[V]) -> V {
+        let act: V = self
+            .weights
+            .iter()
+            .zip(x.iter())
+            .map(|(w, x)| w.clone().mul(x.clone()))
+            .fold(self.bias.clone(), |a, b| a.add(b));
+
+        if self.non_lin {
+            return act.tanh();
+        }
+
+        act
+    }
+
+    /// Like `call`, failing unless there is one input per weight instead of
+    /// ignoring extra inputs or missing weights.
+    pub fn try_call(&self, x: &[V]) -> Result<V, T> {
+        check_len(self.weights.len(), x.len())?;
+        Ok(self.call(x))
+    }
+
+    pub fn call_dual(&self, x: &[Dual<T>]) -> Dual<T> {
+        let act: Dual<T> = self
+            .weights
+            .iter()
+            .zip(x.iter())
+            .map(|(w, &x)| x * w.data())
+            .fold(Dual::constant(self.bias.data()), |a, b| a + b);
+
+        if self.non_lin {
+            return act.tanh();
+        }
+
+        act
+    }
+
+    pub fn parameters(&self) -> Vec<V> {
+        let mut result = self.weights.clone();
+        result.push(self.bias.clone());
+
+        result
+    }
+
+    pub fn parameters_mut(&mut self) -> Vec<&mut V> {
+        let mut result: Vec<&mut V> = self.weights.iter_mut().collect();
+        result.push(&mut self.bias);
+
+        result
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer<T: Float, V: Backend<T>> {
+    neurons: Vec<Neuron<T, V>>,
+}
+
+impl<T: Float, V: Backend<T>> Layer<T, V> {
+    pub fn new(nin: usize, nout: usize, non_lin: bool) -> Layer<T, V> {
+        Layer::with_rng(nin, nout, non_lin, &mut seeded())
+    }
+
+    /// Like `new`, drawing the initial parameters of every neuron from
+    /// `rng` in turn.
+    pub fn with_rng(nin: usize, nout: usize, non_lin: bool, rng: &mut impl Rng) -> Layer<T, V> {
+        Layer {
+            neurons: (0..nout)
+                .map(|_| Neuron::with_rng(nin, non_lin, rng))
+                .collect(),
+        }
+    }
+
+    pub fn call(&self, x: &[V]) -> Vec<V> {
+        self.neurons.iter().map(|n| n.call(x)).collect()
+    }
+
+    pub fn try_call(&self, x: &[V]) -> Result<Vec<V>, T> {
+        self.neurons.iter().map(|n| n.try_call(x)).collect()
+    }
+
+    pub fn call_dual(&self, x: &[Dual<T>]) -> Vec<Dual<T>> {
+        self.neurons.iter().map(|n| n.call_dual(x)).collect()
+    }
+
+    pub fn parameters(&self) -> Vec<V> {
+        self.neurons.iter().flat_map(|n| n.parameters()).collect()
+    }
+
+    pub fn parameters_mut(&mut self) -> Vec<&mut V> {
+        self.neurons
+            .iter_mut()
+            .flat_map(|n| n.parameters_mut())
+            .collect()
+    }
+}
+
+/// An MLP generic over the engine it is built on. `nn::v0::MLP`,
+/// `nn::v1::MLP` and `nn::sync::MLP` are this model on `engine::v0`,
+/// `engine::v1` and `engine::sync`; with the same sizes they all start from
+/// the same parameters, so the engines can be compared on identical models.
+#[derive(Clone, Debug)]
+pub struct MLP<T: Float, V: Backend<T>> {
+    layers: Vec<Layer<T, V>>,
+    nin: usize,
+}
+
+impl<T: Float, V: Backend<T>> MLP<T, V> {
+    pub fn new(nin: usize, nout: Vec<usize>) -> MLP<T, V> {
+        let sz = {
+            let mut sz = vec![nin];
+            sz.extend(&nout);
+            sz
+        };
+
+        let mut rng = seeded();
+        let layers = (0..nout.len())
+            .map(|i| Layer::with_rng(sz[i], sz[i + 1], i != nout.len() - 1, &mut rng))
+            .collect::<Vec<Layer<T, V>>>();
+
+        MLP { layers, nin }
+    }
+
+    pub fn call(&self, x: &[V]) -> Vec<V> {
+        let mut out: Vec<V> = x.to_owned();
+        for layer in self.layers.iter() {
+            out = layer.call(&out);
+        }
+
+        out
+    }
+
+    /// Like `call`, failing if `x` does not have one value per input of the
+    /// first layer.
+    pub fn try_call(&self, x: &[V]) -> Result<Vec<V>, T> {
+        let mut out: Vec<V> = x.to_owned();
+        for layer in self.layers.iter() {
+            out = layer.try_call(&out)?;
+        }
+
+        Ok(out)
+    }
+
+    /// Evaluates the model on dual numbers, treating the parameters as
+    /// constants. Combined with [`crate::engine::dual::jvp`] this gives the
+    /// sensitivity of every output to one input direction in a single pass.
+    pub fn call_dual(&self, x: &[Dual<T>]) -> Vec<Dual<T>> {
+        let mut out: Vec<Dual<T>> = x.to_owned();
+        for layer in self.layers.iter() {
+            out = layer.call_dual(&out);
+        }
+
+        out
+    }
+
+    pub fn loss(&self, xs: Vec<Vec<V>>, ys: Vec<V>) -> V {
+        let mut l: Vec<V> = vec![];
+
+        for (i, x) in xs.iter().enumerate() {
+            let out = self.call(x);
+            let li = out[0].clone().sub(ys[i].clone()).powf(lit(2.0));
+            l.push(li);
+        }
+
+        l.into_iter().fold(V::leaf(T::zero()), |a, b| a.add(b))
+    }
+
+    /// Like `loss`, failing on an empty batch, on a different number of
+    /// samples and targets, on samples that do not fit the model and on
+    /// models without exactly one output.
+    pub fn try_loss(&self, xs: Vec<Vec<V>>, ys: Vec<V>) -> Result<V, T> {
+        if xs.is_empty() {
+            return Err(Error::EmptyDataset);
+        }
+        check_len(xs.len(), ys.len())?;
+
+        let mut l: Vec<V> = vec![];
+        for (x, y) in xs.iter().zip(ys) {
+            let out = self.try_call(x)?;
+            check_len(1, out.len())?;
+            l.push(out[0].clone().sub(y).powf(lit(2.0)));
+        }
+
+        Ok(l.into_iter().fold(V::leaf(T::zero()), |a, b| a.add(b)))
+    }
+
+    /// One step of gradient descent on the squared error over `xs` and
+    /// `ys`: zeroes the gradients, backpropagates the loss and moves every
+    /// parameter by `-rate` times its gradient. Returns the loss before the
+    /// step.
+    pub fn learn(&mut self, xs: Vec<Vec<V>>, ys: Vec<V>, rate: T) -> Result<T, T> {
+        let loss = self.try_loss(xs, ys)?;
+        loss.zero_grad();
+        loss.try_backward()?;
+
+        for p in self.parameters_mut() {
+            let data = p.data() - rate * p.grad();
+            p.set_data(data);
+        }
+
+        Ok(loss.data())
+    }
+
+    pub fn parameters(&self) -> Vec<V> {
+        self.layers.iter().flat_map(|l| l.parameters()).collect()
+    }
+
+    /// The parameters themselves rather than clones, for engines such as
+    /// `engine::v0` whose values can only be updated through `&mut`.
+    pub fn parameters_mut(&mut self) -> Vec<&mut V> {
+        self.layers
+            .iter_mut()
+            .flat_map(|l| l.parameters_mut())
+            .collect()
+    }
+
+    pub(crate) fn nin(&self) -> usize {
+        self.nin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::gradcheck::{self, GradCheck};
+    use crate::engine::{v0, v1};
+    use std::cell::RefCell;
+
+    #[test]
+    fn create_neuron() {
+        let n: Neuron<f64, v0::Value> = Neuron::new(6, true);
+
+        assert_eq!(6, n.weights.len());
+    }
+    #[test]
+    fn create_output_from_neuron() {
+        let seed = 42; // Choose a seed value
+        let mut rng = StdRng::seed_from_u64(seed);
+        let generator = Uniform::from(0.01..=1.00);
+        let x: Vec<v0::Value> = vec![v0::Value::new(generator.sample(&mut rng)); 3];
+
+        let n: Neuron<f64, v0::Value> = Neuron::new(3, true);
+        let out = n.call(&x);
+
+        assert_eq!(3, n.weights.len());
+        assert_eq!(0.0, out.grad);
+    }
+    #[test]
+    fn create_output_from_layer() {
+        let seed = 42; // Choose a seed value
+        let mut rng = StdRng::seed_from_u64(seed);
+        let generator = Uniform::from(0.01..=1.00);
+        let l: Layer<f64, v0::Value> = Layer::new(3, 3, true);
+        let x: Vec<v0::Value> = vec![v0::Value::new(generator.sample(&mut rng)); 3];
+        let out = l.call(&x);
+
+        assert_eq!(3, out.len());
+    }
+    #[test]
+    fn create_output_from_mlp() {
+        let seed = 42; // Choose a seed value
+        let mut rng = StdRng::seed_from_u64(seed);
+        let generator = Uniform::from(0.01..=1.00);
+        let x: Vec<v0::Value> = vec![v0::Value::new(generator.sample(&mut rng)); 3];
+
+        let m: MLP<f64, v0::Value> = MLP::new(2, vec![3, 3, 1]);
+        let out = m.call(&x);
+
+        assert_eq!(1, out.len());
+        assert_eq!(3, m.layers.len());
+    }
+    #[test]
+    fn neurons_start_from_different_parameters() {
+        let m: MLP<f64, v0::Value> = MLP::new(2, vec![3, 1]);
+        let data = |n: &Neuron<f64, v0::Value>| {
+            n.parameters().iter().map(|p| p.data).collect::<Vec<f64>>()
+        };
+        let neurons = &m.layers[0].neurons;
+
+        assert_ne!(data(&neurons[0]), data(&neurons[1]));
+        assert_ne!(data(&neurons[1]), data(&neurons[2]));
+    }
+    #[test]
+    fn engines_agree_on_identical_models() {
+        let m0: MLP<f64, v0::Value> = MLP::new(2, vec![3, 3, 1]);
+        let m1: MLP<f64, v1::Value> = MLP::new(2, vec![3, 3, 1]);
+        let batch = [[0.0, 1.0], [1.0, 0.0], [1.0, 1.0]];
+        let targets = [1.0, 1.0, 0.0];
+
+        fn loss<V: Backend>(m: &MLP<f64, V>, xs: &[[f64; 2]], ys: &[f64]) -> V {
+            let leaves = |xs: &[f64]| xs.iter().map(|&x| V::leaf(x)).collect::<Vec<V>>();
+
+            m.loss(xs.iter().map(|x| leaves(x)).collect(), leaves(ys))
+        }
+        loss(&m0, &batch, &targets).backward();
+        loss(&m1, &batch, &targets).backward();
+
+        for (p0, p1) in m0.parameters().iter().zip(m1.parameters()) {
+            assert_eq!(p0.data, *p1.data.borrow());
+            assert!((p0.total_grad() - *p1.grad.borrow()).abs() < 1e-12);
+        }
+    }
+    #[test]
+    fn try_call_and_try_loss_reject_mismatched_data() {
+        let m: MLP<f64, v1::Value> = MLP::new(2, vec![3, 1]);
+        let x = |xs: &[f64]| xs.iter().map(|&x| v1::Value::new(x)).collect::<Vec<_>>();
+
+        assert_eq!(
+            Some(Error::LengthMismatch {
+                expected: 2,
+                found: 3
+            }),
+            m.try_call(&x(&[1.0, 2.0, 3.0])).err()
+        );
+        assert_eq!(Some(Error::EmptyDataset), m.try_loss(vec![], vec![]).err());
+        assert_eq!(
+            Some(Error::LengthMismatch {
+                expected: 1,
+                found: 2
+            }),
+            m.try_loss(vec![x(&[1.0, 0.0])], x(&[1.0, 0.0])).err()
+        );
+        assert!(m.try_loss(vec![x(&[1.0, 0.0])], x(&[1.0])).is_ok());
+
+        let wide: MLP<f64, v1::Value> = MLP::new(2, vec![3, 2]);
+        assert_eq!(
+            Some(Error::LengthMismatch {
+                expected: 1,
+                found: 2
+            }),
+            wide.try_loss(vec![x(&[1.0, 0.0])], x(&[1.0])).err()
+        );
+    }
+    #[test]
+    fn models_without_neurons_keep_their_input_size() {
+        let empty: MLP<f64, v1::Value> = MLP::new(3, vec![]);
+        let narrow: MLP<f64, v1::Value> = MLP::new(3, vec![0]);
+
+        assert_eq!(3, empty.nin());
+        assert_eq!(3, narrow.nin());
+    }
+    #[test]
+    fn learn_lowers_the_loss_on_both_engines() {
+        fn losses<V: Backend>() -> Vec<f64> {
+            let mut m: MLP<f64, V> = MLP::new(2, vec![3, 1]);
+            let leaves = |xs: &[f64]| xs.iter().map(|&x| V::leaf(x)).collect::<Vec<V>>();
+            let xs = || vec![leaves(&[0.0, 1.0]), leaves(&[1.0, 0.0])];
+
+            (0..20)
+                .map(|_| m.learn(xs(), leaves(&[1.0, -1.0]), 0.05).unwrap())
+                .collect()
+        }
+        let (l0, l1) = (losses::<v0::Value>(), losses::<v1::Value>());
+
+        assert!(l0[19] < l0[0]);
+        for (a, b) in l0.iter().zip(&l1) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+    #[test]
+    fn parameter_gradients_match_finite_differences() {
+        fn check<V: Backend>() -> GradCheck {
+            let m: RefCell<MLP<f64, V>> = RefCell::new(MLP::new(2, vec![3, 1]));
+            let leaves = |xs: &[f64]| xs.iter().map(|&x| V::leaf(x)).collect::<Vec<V>>();
+            let loss = || {
+                let xs = vec![leaves(&[0.5, -1.0]), leaves(&[-0.3, 0.8])];
+                m.borrow().loss(xs, leaves(&[1.0, -1.0]))
+            };
+
+            loss().backward();
+            let params = m.borrow().parameters();
+            let analytic: Vec<f64> = params.iter().map(|p| p.grad()).collect();
+            let data: Vec<f64> = params.iter().map(|p| p.data()).collect();
+
+            let numeric = gradcheck::numeric(
+                |x| {
+                    for (p, &x) in m.borrow_mut().parameters_mut().into_iter().zip(x) {
+                        p.set_data(x);
+                    }
+                    loss().data()
+                },
+                &data,
+            );
+
+            GradCheck {
+                inputs: gradcheck::compare(&analytic, &numeric),
+                op: None,
+            }
+        }
+
+        for report in [check::<v0::Value>(), check::<v1::Value>()] {
+            assert_eq!(9 + 4, report.inputs.len());
+            assert!(report.passed(), "{:?}", report);
+        }
+    }
+}
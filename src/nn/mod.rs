@@ -1,2 +1,5 @@
+pub mod mlp;
+pub mod sync;
 pub mod v0;
 pub mod v1;
+pub mod v2;
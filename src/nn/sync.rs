@@ -0,0 +1,94 @@
+use crate::engine::sync::Value;
+use crate::engine::Float;
+use crate::nn::mlp;
+
+/// `nn::mlp::MLP` on `engine::sync`, so it is `Send + Sync`: any number of
+/// threads can run `call` or `loss(..).backward()` on a shared `&MLP` at
+/// once, and the gradients of every pass are summed into the shared
+/// parameters.
+pub type MLP<T = f64> = mlp::MLP<T, Value<T>>;
+
+impl<T: Float> MLP<T> {
+    pub fn zero_grad(&self) {
+        for p in self.parameters() {
+            p.zero_grad();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn mlp_is_send_and_sync() {
+        fn assert_send_sync<M: Send + Sync>() {}
+
+        assert_send_sync::<MLP>();
+    }
+    #[test]
+    fn starts_from_the_same_parameters_as_other_engines() {
+        let m: MLP = MLP::new(2, vec![3, 1]);
+        let reference: crate::nn::v1::MLP = crate::nn::v1::MLP::new(2, vec![3, 1]);
+        let expected: Vec<f64> = reference
+            .parameters()
+            .iter()
+            .map(|p| *p.data.borrow())
+            .collect();
+
+        assert_eq!(
+            expected,
+            m.parameters()
+                .iter()
+                .map(|p| p.data())
+                .collect::<Vec<f64>>()
+        );
+    }
+    #[test]
+    fn concurrent_inference_matches_sequential() {
+        let m: MLP = MLP::new(2, vec![3, 3, 1]);
+        let x = vec![Value::new(0.5), Value::new(-1.0)];
+        let expected = m.call(&x)[0].data();
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let x = vec![Value::new(0.5), Value::new(-1.0)];
+                    assert_eq!(expected, m.call(&x)[0].data());
+                });
+            }
+        });
+    }
+    #[test]
+    fn concurrent_backward_sums_parameter_gradients() {
+        let m: MLP = MLP::new(2, vec![3, 1]);
+        let sample = |i: usize| {
+            (
+                vec![vec![Value::new(i as f64), Value::new(1.0)]],
+                vec![Value::new(0.0)],
+            )
+        };
+
+        for i in 0..4 {
+            let (xs, ys) = sample(i);
+            m.loss(xs, ys).backward();
+        }
+        let expected: Vec<f64> = m.parameters().iter().map(|p| p.grad()).collect();
+        m.zero_grad();
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let m = &m;
+                s.spawn(move || {
+                    let (xs, ys) = sample(i);
+                    m.loss(xs, ys).backward();
+                });
+            }
+        });
+
+        for (p, e) in m.parameters().iter().zip(expected) {
+            assert!((p.grad() - e).abs() < 1e-12);
+        }
+    }
+}
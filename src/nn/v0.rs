@@ -1,184 +1,234 @@
-use crate::engine::v0::Value;
-use rand::distributions::{Distribution, Uniform};
-use rand::rngs::StdRng;
-use rand::SeedableRng;
-
-trait Module {
-    fn zero_grad(&self) -> Value;
-    fn parameters(&self) -> Vec<Value>;
-}
-
-#[derive(Clone, Debug)]
-struct Neuron {
-    weights: Vec<Value>,
-    bias: Value,
-    non_lin: bool,
-}
-
-impl Neuron {
-    fn new(nin: usize, non_lin: bool) -> Neuron {
-        let seed = 42; // Choose a seed value
-        let mut rng = StdRng::seed_from_u64(seed);
-        let generator = Uniform::from(0.01..=1.00);
-
-        Neuron {
-            weights: vec![Value::new(generator.sample(&mut rng)); nin],
-            bias: Value::new(generator.sample(&mut rng)),
-            non_lin,
-        }
+use crate::engine::program::Program;
+use crate::engine::v0::{trace, Value};
+use crate::engine::Float;
+use crate::nn::mlp;
+
+/// `nn::mlp::MLP` on `engine::v0`, which can also be traced into a
+/// `Program`.
+pub type MLP<T = f64> = mlp::MLP<T, Value<T>>;
+
+impl<T: Float> MLP<T> {
+    /// Traces `call` into a `Program` whose inputs are the model's inputs
+    /// and whose params are `parameters()`, so repeated inference reuses one
+    /// set of slots instead of building a graph per call.
+    pub fn compile(&self) -> Program<T> {
+        let x: Vec<Value<T>> = (0..self.nin()).map(|_| Value::new(T::zero())).collect();
+
+        trace(&self.call(&x), &x, &self.parameters())
     }
 
-    fn call(&self, x: &[Value]) -> Value {
-        let act: Value = self
-            .weights
-            .iter()
-            .zip(x.iter())
-            .map(move |(x, y)| x * y)
-            .fold(self.bias.clone(), |a, b| a + b);
+    /// Traces `loss` over a batch of `batch` samples. The inputs of the
+    /// program are the features of each sample in turn, then the targets.
+    pub fn compile_loss(&self, batch: usize) -> Program<T> {
+        let xs: Vec<Vec<Value<T>>> = (0..batch)
+            .map(|_| (0..self.nin()).map(|_| Value::new(T::zero())).collect())
+            .collect();
+        let ys: Vec<Value<T>> = (0..batch).map(|_| Value::new(T::zero())).collect();
+        let inputs: Vec<Value<T>> = xs.iter().flatten().chain(ys.iter()).cloned().collect();
 
-        if self.non_lin {
-            return act.tanh();
-        }
-
-        act
-    }
-
-    fn _parameters(&self) -> Vec<Value> {
-        let mut result = self.weights.clone();
-        result.push(self.bias.clone());
-
-        result
+        trace(&[self.loss(xs, ys)], &inputs, &self.parameters())
     }
-}
-
-struct Layer {
-    neurons: Vec<Neuron>,
-}
 
-impl Layer {
-    fn new(nin: usize, nout: usize, non_lin: bool) -> Layer {
-        Layer {
-            neurons: vec![Neuron::new(nin, non_lin); nout],
-        }
+    /// Standalone Rust source for `call` with the current parameters baked
+    /// in, and with `gradients` for its gradients with respect to the
+    /// inputs and parameters; see `Program::to_rust`.
+    pub fn to_rust(&self, name: &str, gradients: bool) -> String {
+        self._optimized()
+            .to_rust(name, gradients)
+            .expect("an MLP has no custom ops")
     }
 
-    fn call(&self, x: &[Value]) -> Vec<Value> {
-        self.neurons.iter().map(move |n| n.call(x)).collect()
+    /// Like `to_rust` but C source; see `Program::to_c`.
+    pub fn to_c(&self, name: &str, gradients: bool) -> String {
+        self._optimized()
+            .to_c(name, gradients)
+            .expect("an MLP has no custom ops")
     }
 
-    fn _parameters(&self) -> Vec<Value> {
-        let mut result = vec![];
-
-        for n in self.neurons.iter() {
-            let mut params = n._parameters().clone();
-            result.append(&mut params);
-        }
-
-        result
+    fn _optimized(&self) -> Program<T> {
+        let mut program = self.compile();
+        program.optimize();
+        program
     }
 }
 
-pub struct MLP {
-    layers: Vec<Layer>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::dual::jvp;
+    use crate::engine::v0::gradcheck;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
-impl MLP {
-    pub fn new(nin: usize, nout: Vec<usize>) -> MLP {
-        let sz = {
-            let mut sz = vec![nin];
-            sz.extend(&nout);
-            sz
-        };
+    #[test]
+    fn create_single_precision_mlp() {
+        let x: Vec<Value<f32>> = vec![Value::new(0.5), Value::new(1.0)];
 
-        let layers = (0..nout.len())
-            .map(|i| Layer::new(sz[i], sz[i + 1], i != nout.len() - 1))
-            .collect::<Vec<Layer>>();
+        let m: MLP<f32> = MLP::new(2, vec![3, 3, 1]);
+        let out = m.call(&x);
 
-        MLP { layers }
+        assert_eq!(1, out.len());
+        assert!(out[0].data.is_finite());
     }
+    #[test]
+    fn call_dual_matches_call_and_finite_differences() {
+        let m: MLP = MLP::new(2, vec![3, 3, 1]);
+        let x = [0.3, -0.7];
+        let h = 1e-6;
 
-    pub fn call(&self, x: &[Value]) -> Vec<Value> {
-        let mut out: Vec<Value> = x.to_owned();
-        for layer in self.layers.iter() {
-            out = layer.call(&out);
-        }
+        let (out, tangent) = jvp(|x| m.call_dual(x), &x, &[1.0, 0.0]);
+        let f = |x0: f64| m.call(&[Value::new(x0), Value::new(x[1])])[0].data;
 
-        out
+        assert_eq!(f(x[0]), out[0]);
+        assert!(((f(x[0] + h) - f(x[0] - h)) / (2.0 * h) - tangent[0]).abs() < 1e-6);
     }
+    #[test]
+    fn gradcheck_loss() {
+        let m: MLP = MLP::new(2, vec![3, 3, 1]);
 
-    pub fn loss(&self, xs: Vec<Vec<Value>>, ys: Vec<Value>) -> Value {
-        let mut l: Vec<Value> = vec![];
-
-        for (i, x) in xs.iter().enumerate() {
-            let out = self.call(x);
-            let li = (out[0].clone() - ys[i].clone()).powf(2.0);
-            l.push(li);
-        }
+        let report = gradcheck(
+            |x| m.loss(vec![x.to_vec()], vec![Value::new(1.0)]),
+            &[0.3, -0.7],
+        );
 
-        l.iter().fold(Value::new(0.0), |a, b| a + b.clone())
+        assert!(report.passed(), "{:?}", report);
     }
+    #[test]
+    fn call_under_no_grad_matches_call() {
+        let m: MLP = MLP::new(2, vec![3, 3, 1]);
+        let x = vec![Value::new(0.3), Value::new(-0.7)];
 
-    pub fn parameters(&self) -> Vec<Value> {
-        let mut result = vec![];
-
-        for l in self.layers.iter() {
-            let mut params = l._parameters().clone();
-            result.append(&mut params);
-        }
+        let tracked = m.call(&x);
+        let _guard = crate::engine::no_grad();
+        let untracked = m.call(&x);
 
-        result
+        assert_eq!(tracked[0].data, untracked[0].data);
+        assert!(untracked[0]._prev.is_empty());
     }
+    #[test]
+    fn compiled_call_matches_call() {
+        let m: MLP = MLP::new(2, vec![3, 3, 1]);
+        let mut program = m.compile();
 
-    pub fn learn(self) -> MLP {
-        unimplemented!()
+        for x in [[0.3, -0.7], [1.0, 2.0]] {
+            program.forward(&x);
+            let out = m.call(&[Value::new(x[0]), Value::new(x[1])]);
+
+            assert_eq!(vec![out[0].data], program.outputs());
+        }
     }
-}
+    #[test]
+    fn models_without_neurons_compile() {
+        let mut program = MLP::new(2, vec![]).compile();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        program.forward(&[0.3, -0.7]);
 
+        assert_eq!(vec![0.3, -0.7], program.outputs());
+        assert!(MLP::<f64>::new(2, vec![0]).compile().outputs().is_empty());
+    }
     #[test]
-    fn create_neuron() {
-        let n = Neuron::new(6, true);
+    fn compiled_loss_keeps_every_parameter_apart() {
+        let m: MLP = MLP::new(2, vec![3, 3, 1]);
+        let program = m.compile_loss(2);
 
-        assert_eq!(6, n.weights.len());
+        assert_eq!(9 + 12 + 4, m.parameters().len());
+        assert_eq!(m.parameters().len(), program.param_grads().len());
     }
     #[test]
-    fn create_output_from_neuron() {
-        let seed = 42; // Choose a seed value
-        let mut rng = StdRng::seed_from_u64(seed);
-        let generator = Uniform::from(0.01..=1.00);
-        let x: Vec<Value> = vec![Value::new(generator.sample(&mut rng)); 3];
+    fn compiled_loss_trains_without_rebuilding() {
+        let m: MLP = MLP::new(2, vec![3, 1]);
+        let mut program = m.compile_loss(2);
+        let batch = [0.0, 1.0, 1.0, 0.0, 1.0, 0.0];
+
+        program.forward(&batch);
+        program.backward();
+        let numeric = crate::engine::gradcheck::numeric(
+            |p| {
+                let mut program = m.compile_loss(2);
+                program.set_params(p);
+                program.forward(&batch);
+                program.outputs()[0]
+            },
+            &program.params(),
+        );
+        for (a, n) in program.param_grads().iter().zip(numeric) {
+            assert!((a - n).abs() < 1e-6);
+        }
 
-        let n = Neuron::new(3, true);
-        let out = n.call(&x);
+        let before = program.outputs()[0];
+        for _ in 0..10 {
+            let params: Vec<f64> = program
+                .params()
+                .iter()
+                .zip(program.param_grads())
+                .map(|(p, g)| p - 0.05 * g)
+                .collect();
+            program.set_params(&params);
+            program.forward(&batch);
+            program.backward();
+        }
 
-        assert_eq!(3, n.weights.len());
-        assert_eq!(0.0, out.grad);
+        assert!(program.outputs()[0] < before);
     }
     #[test]
-    fn create_output_from_layer() {
-        let seed = 42; // Choose a seed value
-        let mut rng = StdRng::seed_from_u64(seed);
-        let generator = Uniform::from(0.01..=1.00);
-        let l = Layer::new(3, 3, true);
-        let x: Vec<Value> = vec![Value::new(generator.sample(&mut rng)); 3];
-        let out = l.call(&x);
+    fn optimized_loss_matches_and_shrinks() {
+        let m: MLP = MLP::new(2, vec![3, 3, 1]);
+        let mut program = m.compile_loss(4);
+        let mut optimized = program.clone();
+        let batch = [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0];
+
+        let report = optimized.optimize();
+        for p in [&mut program, &mut optimized] {
+            p.forward(&batch);
+            p.backward();
+        }
 
-        assert_eq!(3, out.len());
+        assert!(report.after < report.before);
+        assert_eq!(4, report.simplified / 2);
+        assert!((program.outputs()[0] - optimized.outputs()[0]).abs() < 1e-12);
+        for (a, b) in program.param_grads().iter().zip(optimized.param_grads()) {
+            assert!((a - b).abs() < 1e-12);
+        }
     }
     #[test]
-    fn create_output_from_mlp() {
-        let seed = 42; // Choose a seed value
-        let mut rng = StdRng::seed_from_u64(seed);
-        let generator = Uniform::from(0.01..=1.00);
-        let x: Vec<Value> = vec![Value::new(generator.sample(&mut rng)); 3];
+    fn hooks_clip_parameter_gradients() {
+        let m: MLP = MLP::new(2, vec![3, 1]);
+        let seen = Rc::new(RefCell::new(vec![]));
+        for p in m.parameters() {
+            let log = seen.clone();
+            p.register_hook(move |g: f64| {
+                log.borrow_mut().push(g);
+                g.clamp(-0.01, 0.01)
+            });
+        }
+        let xs = vec![vec![Value::new(3.0), Value::new(-2.0)]];
 
-        let m = MLP::new(2, vec![3, 3, 1]);
-        let out: Vec<Value> = m.call(&x);
+        m.loss(xs, vec![Value::new(5.0)]).backward();
 
-        assert_eq!(1, out.len());
-        assert_eq!(3, m.layers.len());
+        assert!(!seen.borrow().is_empty());
+        assert!(seen.borrow().iter().any(|g| g.abs() > 0.01));
+    }
+    #[test]
+    fn backward_reaches_model_parameters() {
+        let mut m: MLP = MLP::new(2, vec![3, 1]);
+        let xs = || vec![vec![Value::new(1.0), Value::new(-2.0)]];
+        let ys = || vec![Value::new(0.5)];
+
+        let loss = m.loss(xs(), ys()).backward();
+        let before = loss.data;
+        let mut program = m.compile_loss(1);
+        program.forward(&[1.0, -2.0, 0.5]);
+        program.backward();
+
+        for (p, g) in m.parameters().iter().zip(program.param_grads()) {
+            assert!((p.total_grad() - g).abs() < 1e-12);
+        }
+        for p in m.parameters_mut() {
+            p.data -= 0.1 * p.total_grad();
+        }
+        loss.zero_grad();
+
+        assert!(m.loss(xs(), ys()).data < before);
+        assert!(m.parameters().iter().all(|p| p.total_grad() == 0.0));
     }
 }
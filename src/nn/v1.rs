@@ -1,164 +1,55 @@
-#![allow(dead_code)]
-
 use crate::engine::v1::Value;
-use rand::distributions::{Distribution, Uniform};
-use rand::rngs::StdRng;
-use rand::SeedableRng;
-use std::cell::RefCell;
-use std::rc::Rc;
-
-trait Module {
-    fn zero_grad(&self) -> Value;
-    fn parameters(&self) -> Vec<Value>;
-}
-
-#[derive(Debug)]
-struct Neuron {
-    weights: Vec<Rc<RefCell<Value>>>,
-    bias: Rc<RefCell<Value>>,
-    non_lin: bool,
-}
-
-impl Neuron {
-    fn new(nin: usize, non_lin: bool) -> Neuron {
-        let seed = 42; // Choose a seed value
-        let mut rng = StdRng::seed_from_u64(seed);
-        let generator = Uniform::from(0.01..=1.00);
-
-        Neuron {
-            weights: {
-                let mut v = Vec::with_capacity(nin);
-                (0..nin).for_each(|_| {
-                    v.push(Rc::new(RefCell::new(Value::new(
-                        generator.sample(&mut rng),
-                    ))))
-                });
-                v
-            },
-            bias: Rc::new(RefCell::new(Value::new(generator.sample(&mut rng)))),
-            non_lin,
-        }
-    }
-
-    fn call(&self, xs: Vec<Rc<RefCell<Value>>>) -> Value {
-        let act = self
-            .weights
-            .iter()
-            .zip(xs.iter())
-            .map(|(x, y)| -> Value {
-                let a: Value = (*x.borrow()).to_owned();
-                let b: Value = (*y.borrow()).to_owned();
-
-                a * b
-            })
-            .fold(self.bias.borrow().to_owned(), |a, b| a + b);
-
-        if self.non_lin {
-            return act.tanh();
-        }
-
-        act
-    }
-
-    fn _parameters(self) -> Vec<Rc<RefCell<Value>>> {
-        let mut result: Vec<Rc<RefCell<Value>>> =
-            self.weights.into_iter().map(|v| Rc::clone(&v)).collect();
-        result.push(Rc::clone(&self.bias));
+use crate::nn::mlp;
 
-        result
-    }
-}
-
-struct Layer {
-    neurons: Vec<Rc<RefCell<Neuron>>>,
-}
-
-impl Layer {
-    fn new(nin: usize, nout: usize, non_lin: bool) -> Layer {
-        Layer {
-            neurons: {
-                let mut v = Vec::with_capacity(nin);
-                (0..nout).for_each(|_| v.push(Rc::new(RefCell::new(Neuron::new(nin, non_lin)))));
-                v
-            },
-        }
-    }
-
-    fn call(&self, xs: Vec<Rc<RefCell<Value>>>) -> Vec<Value> {
-        self.neurons
-            .iter()
-            .map(|n| n.borrow().call(xs.clone()))
-            .collect()
-    }
-
-    fn _parameters(&self) -> Vec<Rc<RefCell<Value>>> {
-        unimplemented!()
-    }
-}
+/// `nn::mlp::MLP` on `engine::v1`, whose parameters receive their
+/// gradients in place.
+pub type MLP<T = f64> = mlp::MLP<T, Value<T>>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nn::mlp::Neuron;
+    use rand::distributions::{Distribution, Uniform};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn create_neuron_adds_correct_amount_of_weights() {
-        let n = Neuron::new(6, true);
+        let n: Neuron<f64, Value> = Neuron::new(6, true);
 
-        assert_eq!(6, n.weights.len());
+        assert_eq!(6 + 1, n.parameters().len());
     }
     #[test]
-    fn create_neuron_adds_random_value_weights() {
-        let n = Neuron::new(6, true);
+    fn create_neuron_adds_random_value_weights_and_bias() {
+        let n: Neuron<f64, Value> = Neuron::new(6, true);
 
-        n.weights.iter().for_each(|v| {
-            assert_ne!(0.0, *v.borrow().data.borrow());
+        n.parameters().iter().for_each(|v| {
+            assert_ne!(0.0, *v.data.borrow());
         });
     }
     #[test]
-    fn create_neuron_adds_random_value_bias() {
-        let n = Neuron::new(6, true);
-
-        assert_ne!(0.0, *n.bias.borrow().data.borrow());
-    }
-    #[test]
     fn create_output_from_neuron() {
         let seed = 42; // Choose a seed value
         let mut rng = StdRng::seed_from_u64(seed);
         let generator = Uniform::from(0.01..=1.00);
-        let x: Vec<Rc<RefCell<Value>>> = {
-            let mut v = Vec::with_capacity(3);
-            (0..3).for_each(|_| {
-                v.push(Rc::new(RefCell::new(Value::new(
-                    generator.sample(&mut rng),
-                ))))
-            });
-            v
-        };
+        let x: Vec<Value> = (0..3)
+            .map(|_| Value::new(generator.sample(&mut rng)))
+            .collect();
 
-        let n = Neuron::new(3, true);
-        let out = n.call(x);
+        let n: Neuron<f64, Value> = Neuron::new(3, true);
+        let out = n.call(&x);
 
         assert_eq!(0.0, *out.grad.borrow());
     }
     #[test]
-    fn output_grad_not_zero_after_backward_from_neuron() {
-        let seed = 42; // Choose a seed value
-        let mut rng = StdRng::seed_from_u64(seed);
-        let generator = Uniform::from(0.01..=1.00);
-        let x: Vec<Rc<RefCell<Value>>> = {
-            let mut v = Vec::with_capacity(3);
-            (0..3).for_each(|_| {
-                v.push(Rc::new(RefCell::new(Value::new(
-                    generator.sample(&mut rng),
-                ))))
-            });
-            v
-        };
+    fn parameter_grads_not_zero_after_backward_from_mlp() {
+        let m: MLP = MLP::new(2, vec![3, 1]);
+        let xs = vec![vec![Value::new(0.5), Value::new(-1.0)]];
 
-        let n = Neuron::new(3, true);
-        let out = n.call(x);
-        out.backward();
+        m.loss(xs, vec![Value::new(1.0)]).backward();
 
-        assert_ne!(0.0, *out.grad.borrow());
+        for p in m.parameters() {
+            assert_ne!(0.0, *p.grad.borrow());
+        }
     }
 }
@@ -0,0 +1,211 @@
+use crate::engine::v2::{Tape, Value};
+use crate::engine::{lit, Float};
+use crate::nn::mlp::seeded;
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+
+#[derive(Clone, Debug)]
+struct Neuron<'t, T: Float = f64> {
+    weights: Vec<Value<'t, T>>,
+    bias: Value<'t, T>,
+    non_lin: bool,
+}
+
+impl<'t, T: Float> Neuron<'t, T> {
+    fn new(tape: &'t Tape<T>, nin: usize, non_lin: bool, rng: &mut impl Rng) -> Neuron<'t, T> {
+        let generator = Uniform::from(0.01..=1.00);
+
+        Neuron {
+            weights: (0..nin)
+                .map(|_| tape.value(lit(generator.sample(rng))))
+                .collect(),
+            bias: tape.value(lit(generator.sample(rng))),
+            non_lin,
+        }
+    }
+
+    fn call(&self, x: &[Value<'t, T>]) -> Value<'t, T> {
+        let act: Value<'t, T> = self
+            .weights
+            .iter()
+            .zip(x.iter())
+            .map(|(&w, &x)| w * x)
+            .fold(self.bias, |a, b| a + b);
+
+        if self.non_lin {
+            return act.tanh();
+        }
+
+        act
+    }
+
+    fn _parameters(&self) -> Vec<Value<'t, T>> {
+        let mut result = self.weights.clone();
+        result.push(self.bias);
+
+        result
+    }
+}
+
+struct Layer<'t, T: Float = f64> {
+    neurons: Vec<Neuron<'t, T>>,
+}
+
+impl<'t, T: Float> Layer<'t, T> {
+    fn new(
+        tape: &'t Tape<T>,
+        nin: usize,
+        nout: usize,
+        non_lin: bool,
+        rng: &mut impl Rng,
+    ) -> Layer<'t, T> {
+        Layer {
+            neurons: (0..nout)
+                .map(|_| Neuron::new(tape, nin, non_lin, rng))
+                .collect(),
+        }
+    }
+
+    fn call(&self, x: &[Value<'t, T>]) -> Vec<Value<'t, T>> {
+        self.neurons.iter().map(|n| n.call(x)).collect()
+    }
+
+    fn _parameters(&self) -> Vec<Value<'t, T>> {
+        self.neurons.iter().flat_map(|n| n._parameters()).collect()
+    }
+}
+
+/// An MLP whose parameters are the first nodes pushed onto `tape`. Everything
+/// a forward pass adds after them can be dropped with [`MLP::reset`], so the
+/// same tape is reused across training steps.
+pub struct MLP<'t, T: Float = f64> {
+    tape: &'t Tape<T>,
+    layers: Vec<Layer<'t, T>>,
+    len: usize,
+}
+
+impl<'t, T: Float> MLP<'t, T> {
+    pub fn new(tape: &'t Tape<T>, nin: usize, nout: Vec<usize>) -> MLP<'t, T> {
+        let sz = {
+            let mut sz = vec![nin];
+            sz.extend(&nout);
+            sz
+        };
+
+        let mut rng = seeded();
+        let layers = (0..nout.len())
+            .map(|i| Layer::new(tape, sz[i], sz[i + 1], i != nout.len() - 1, &mut rng))
+            .collect::<Vec<Layer<'t, T>>>();
+
+        MLP {
+            tape,
+            layers,
+            len: tape.len(),
+        }
+    }
+
+    pub fn call(&self, x: &[Value<'t, T>]) -> Vec<Value<'t, T>> {
+        let mut out: Vec<Value<'t, T>> = x.to_owned();
+        for layer in self.layers.iter() {
+            out = layer.call(&out);
+        }
+
+        out
+    }
+
+    pub fn loss(&self, xs: Vec<Vec<Value<'t, T>>>, ys: Vec<Value<'t, T>>) -> Value<'t, T> {
+        let mut l: Vec<Value<'t, T>> = vec![];
+
+        for (i, x) in xs.iter().enumerate() {
+            let out = self.call(x);
+            let li = (out[0] - ys[i]).powf(lit(2.0));
+            l.push(li);
+        }
+
+        l.into_iter().fold(self.tape.value(T::zero()), |a, b| a + b)
+    }
+
+    pub fn parameters(&self) -> Vec<Value<'t, T>> {
+        self.layers.iter().flat_map(|l| l._parameters()).collect()
+    }
+
+    /// Drops every node created since the parameters and zeroes their grads.
+    /// Handles to the dropped nodes, such as inputs, outputs or the loss of
+    /// the last step, panic if used afterwards.
+    pub fn reset(&self) {
+        self.tape.truncate(self.len);
+        self.tape.zero_grad();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_neuron() {
+        let tape: Tape = Tape::new();
+        let n: Neuron = Neuron::new(&tape, 6, true, &mut seeded());
+
+        assert_eq!(6, n.weights.len());
+        assert_eq!(7, tape.len());
+    }
+    #[test]
+    fn create_output_from_mlp() {
+        let tape: Tape = Tape::new();
+        let x = vec![tape.value(0.5); 2];
+
+        let m = MLP::new(&tape, 2, vec![3, 3, 1]);
+        let out: Vec<Value> = m.call(&x);
+
+        assert_eq!(1, out.len());
+        assert_eq!(3, m.layers.len());
+    }
+    #[test]
+    fn loss_backward_reaches_parameters() {
+        let tape: Tape = Tape::new();
+        let m = MLP::new(&tape, 2, vec![3, 1]);
+        let xs = vec![vec![tape.value(0.0), tape.value(1.0)]];
+        let ys = vec![tape.value(1.0)];
+
+        let loss = m.loss(xs, ys);
+        loss.backward();
+
+        assert!(m.parameters().iter().any(|p| p.grad() != 0.0));
+    }
+    #[test]
+    fn reset_keeps_only_parameters() {
+        let tape: Tape = Tape::new();
+        let m = MLP::new(&tape, 2, vec![3, 1]);
+        let params = tape.len();
+        let x = vec![tape.value(0.0), tape.value(1.0)];
+
+        m.call(&x)[0].backward();
+        m.reset();
+
+        assert_eq!(params, tape.len());
+        assert!(m.parameters().iter().all(|p| p.grad() == 0.0));
+    }
+    #[test]
+    fn neurons_start_from_different_parameters() {
+        let tape: Tape = Tape::new();
+        let m = MLP::new(&tape, 2, vec![2, 1]);
+        let [a, b] = [&m.layers[0].neurons[0], &m.layers[0].neurons[1]];
+
+        assert_ne!(a.bias.data(), b.bias.data());
+        assert_ne!(a.weights[0].data(), b.weights[0].data());
+    }
+    #[test]
+    #[should_panic(expected = "dropped from its tape")]
+    fn inputs_from_before_reset_cannot_be_read() {
+        let tape: Tape = Tape::new();
+        let m = MLP::new(&tape, 2, vec![3, 1]);
+        let x = vec![tape.value(7.0), tape.value(1.0)];
+
+        m.call(&x)[0].backward();
+        m.reset();
+        let _ = tape.value(-99.0);
+
+        x[0].data();
+    }
+}